@@ -0,0 +1,122 @@
+use serde_json::{json, Value};
+use std::str::FromStr;
+use ethers_core::types::{Address, I256, U256};
+use ethers_core::abi::{Function, ParamType, Token};
+use ethers_core::utils::hex;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Converts JSON arguments to tokens of the given types. Addresses, integers, bytes and strings are
+// JSON strings (integers may also be numbers), arrays and tuples are JSON arrays.
+pub fn tokenize_json(kinds: &[ParamType], values: &[Value]) -> Result<Vec<Token>> {
+	if kinds.len() != values.len() {
+		return Err(format!("Expected {} arguments, got {}", kinds.len(), values.len()).into());
+	}
+	kinds.iter().zip(values).map(|(kind, value)| json_to_token(kind, value)).collect()
+}
+
+pub fn json_to_token(kind: &ParamType, value: &Value) -> Result<Token> {
+	let invalid = || format!("Invalid {} argument: {}", kind, value);
+	let token = match kind {
+		ParamType::Address => Token::Address(Address::from_str(value.as_str().ok_or_else(invalid)?)?),
+		ParamType::Bytes => Token::Bytes(decode_hex(value.as_str().ok_or_else(invalid)?)?),
+		ParamType::FixedBytes(len) => {
+			let bytes = decode_hex(value.as_str().ok_or_else(invalid)?)?;
+			if bytes.len() != *len {
+				return Err(invalid().into());
+			}
+			Token::FixedBytes(bytes)
+		}
+		ParamType::Uint(size) => Token::Uint(parse_uint(value).filter(|n| uint_fits(*n, *size)).ok_or_else(invalid)?),
+		ParamType::Int(size) => Token::Int(parse_int(value).filter(|n| int_fits(*n, *size)).ok_or_else(invalid)?.into_raw()),
+		ParamType::Bool => match value {
+			Value::Bool(b) => Token::Bool(*b),
+			Value::String(s) if s == "true" || s == "false" => Token::Bool(s == "true"),
+			_ => return Err(invalid().into()),
+		},
+		ParamType::String => Token::String(value.as_str().ok_or_else(invalid)?.to_string()),
+		ParamType::Array(inner) => {
+			let items = value.as_array().ok_or_else(invalid)?;
+			Token::Array(items.iter().map(|item| json_to_token(inner, item)).collect::<Result<_>>()?)
+		}
+		ParamType::FixedArray(inner, len) => {
+			let items = value.as_array().filter(|items| items.len() == *len).ok_or_else(invalid)?;
+			Token::FixedArray(items.iter().map(|item| json_to_token(inner, item)).collect::<Result<_>>()?)
+		}
+		ParamType::Tuple(kinds) => Token::Tuple(tokenize_json(kinds, value.as_array().ok_or_else(invalid)?)?),
+	};
+	Ok(token)
+}
+
+pub fn token_to_json(token: &Token) -> Value {
+	match token {
+		Token::Address(address) => json!(format!("{:?}", address)),
+		Token::Bytes(bytes) | Token::FixedBytes(bytes) => json!(format!("0x{}", hex::encode(bytes))),
+		Token::Uint(n) => json!(n.to_string()),
+		Token::Int(n) => json!(I256::from_raw(*n).to_string()),
+		Token::Bool(b) => json!(b),
+		Token::String(s) => json!(s),
+		Token::Array(items) | Token::FixedArray(items) | Token::Tuple(items) => Value::Array(items.iter().map(token_to_json).collect()),
+	}
+}
+
+// Decoded outputs as a JSON object keyed by output name, or as an array when any output is unnamed.
+pub fn outputs_to_json(function: &Function, tokens: &[Token]) -> Value {
+	if !function.outputs.is_empty() && function.outputs.iter().all(|output| !output.name.is_empty()) {
+		let outputs = function.outputs.iter().zip(tokens).map(|(output, token)| (output.name.clone(), token_to_json(token)));
+		Value::Object(outputs.collect())
+	} else {
+		Value::Array(tokens.iter().map(token_to_json).collect())
+	}
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>> {
+	Ok(hex::decode(s.trim_start_matches("0x"))?)
+}
+
+fn parse_uint(value: &Value) -> Option<U256> {
+	match value {
+		Value::Number(n) => n.as_u64().map(U256::from),
+		Value::String(s) if s.starts_with("0x") => U256::from_str(s).ok(),
+		Value::String(s) => U256::from_dec_str(s).ok(),
+		_ => None,
+	}
+}
+
+// ethabi encodes any 256-bit value whatever the declared size, which the contract would then reject or misread.
+fn uint_fits(n: U256, size: usize) -> bool {
+	n.bits() <= size
+}
+
+fn int_fits(n: I256, size: usize) -> bool {
+	if size >= 256 {
+		return true;
+	}
+	let max = I256::from_raw((U256::one() << (size - 1)) - 1);
+	n <= max && n >= -max - I256::one()
+}
+
+fn parse_int(value: &Value) -> Option<I256> {
+	match value {
+		Value::Number(n) => n.as_i64().map(I256::from),
+		Value::String(s) => I256::from_dec_str(s).ok(),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejects_integers_outside_declared_size() {
+		assert!(json_to_token(&ParamType::Uint(8), &json!("255")).is_ok());
+		assert!(json_to_token(&ParamType::Uint(8), &json!("1000")).is_err());
+		assert!(json_to_token(&ParamType::Uint(256), &json!(format!("{}", U256::MAX))).is_ok());
+		assert!(json_to_token(&ParamType::Int(8), &json!(-128)).is_ok());
+		assert!(json_to_token(&ParamType::Int(8), &json!("127")).is_ok());
+		assert!(json_to_token(&ParamType::Int(8), &json!("-1000")).is_err());
+		assert!(json_to_token(&ParamType::Int(8), &json!(128)).is_err());
+		assert!(json_to_token(&ParamType::Int(256), &json!(I256::MIN.to_string())).is_ok());
+	}
+}
@@ -0,0 +1,161 @@
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use ethers_core::types::{Address, Bytes, H256};
+use ethers_core::abi::{ethabi, Abi, AbiParser, Event, Function, Token};
+
+mod json;
+pub use json::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const PBM_ABI: &str = include_str!("pbm.json");
+
+pub struct Contract {
+	pub name: String,
+	pub address: Option<Address>,
+	pub abi: Abi,
+}
+
+#[derive(Default)]
+pub struct AbiRegistry {
+	contracts: Vec<Contract>,
+}
+
+impl AbiRegistry {
+	pub fn new() -> Self {
+		AbiRegistry::default()
+	}
+
+	// Registers the built-in PBM ABI at CONTRACT_ADDRESS, then every entry of the ABI_REGISTRY env variable.
+	// ABI_REGISTRY is a JSON array of {"name", "address", "abi"} or {"name", "address", "abi_file"} objects.
+	pub fn from_env() -> Self {
+		let mut registry = AbiRegistry::new();
+		let pbm_address = std::env::var("CONTRACT_ADDRESS").unwrap_or("0xb1C1cEE9952e99f1d114f80E6a17fD598Ef106Af".to_string());
+		registry.register_json("pbm", Address::from_str(&pbm_address).ok(), PBM_ABI).expect("Failed to parse built-in PBM ABI");
+
+		if let Ok(config) = std::env::var("ABI_REGISTRY") {
+			if let Err(err) = registry.register_config(&config) {
+				log::error!("Failed to load ABI_REGISTRY: {}", err);
+			}
+		}
+		registry
+	}
+
+	pub fn register(&mut self, name: &str, address: Option<Address>, abi: Abi) {
+		self.contracts.retain(|contract| contract.name != name);
+		self.contracts.push(Contract { name: name.to_string(), address, abi });
+	}
+
+	pub fn register_json(&mut self, name: &str, address: Option<Address>, abi_json: &str) -> Result<()> {
+		let abi: Abi = serde_json::from_str(abi_json)?;
+		self.register(name, address, abi);
+		Ok(())
+	}
+
+	pub fn register_file(&mut self, name: &str, address: Option<Address>, path: &str) -> Result<()> {
+		let abi_json = std::fs::read_to_string(path)?;
+		self.register_json(name, address, &abi_json)
+	}
+
+	pub fn register_config(&mut self, config: &str) -> Result<()> {
+		let entries: Vec<Value> = serde_json::from_str(config)?;
+		for entry in entries {
+			let name = entry["name"].as_str().ok_or("ABI registry entry requires a name")?;
+			let address = match entry["address"].as_str() {
+				Some(address) => Some(Address::from_str(address)?),
+				None => None,
+			};
+			match (&entry["abi"], entry["abi_file"].as_str()) {
+				(Value::String(abi_json), _) => self.register_json(name, address, abi_json)?,
+				(Value::Array(_), _) => self.register(name, address, serde_json::from_value(entry["abi"].clone())?),
+				(_, Some(path)) => self.register_file(name, address, path)?,
+				_ => return Err(format!("ABI registry entry {} requires abi or abi_file", name).into()),
+			}
+		}
+		Ok(())
+	}
+
+	pub fn contracts(&self) -> &[Contract] {
+		&self.contracts
+	}
+
+	// Looks a contract up by registered name or by address.
+	pub fn get(&self, contract: &str) -> Option<&Contract> {
+		match Address::from_str(contract) {
+			Ok(address) => self.contracts.iter().find(|c| c.address == Some(address)),
+			Err(_) => self.contracts.iter().find(|c| c.name == contract),
+		}
+	}
+
+	// Resolves `function` as a plain name, or as a signature such as `transfer(address,uint256)`
+	// when the contract overloads that name.
+	pub fn function(&self, contract: &str, function: &str) -> Result<&Function> {
+		let abi = &self.get(contract).ok_or_else(|| format!("Contract {} is not registered", contract))?.abi;
+		let function = function.replace(' ', "");
+		match function.split_once('(') {
+			Some((name, _)) => abi
+				.functions_by_name(name)?
+				.iter()
+				.find(|f| input_signature(f) == function)
+				.ok_or_else(|| format!("Function {} not found in ABI of {}", function, contract).into()),
+			None => match abi.functions_by_name(&function)?.as_slice() {
+				[f] => Ok(f),
+				overloads => Err(format!("Function {} is overloaded, use one of: {}", function,
+					overloads.iter().map(input_signature).collect::<Vec<_>>().join(", ")).into()),
+			},
+		}
+	}
+
+	// Like `function`, but for an unregistered contract `function` is parsed as a human-readable
+	// signature such as `balanceOf(address) returns (uint256)`.
+	pub fn resolve_function(&self, contract: &str, function: &str) -> Result<Function> {
+		if self.get(contract).is_some() {
+			return Ok(self.function(contract, function)?.clone());
+		}
+		AbiParser::default()
+			.parse_function(function)
+			.map_err(|err| format!("Contract {} is not registered and {} is not a function signature: {}", contract, function, err).into())
+	}
+
+	pub fn event(&self, contract: &str, event: &str) -> Result<&Event> {
+		let abi = &self.get(contract).ok_or_else(|| format!("Contract {} is not registered", contract))?.abi;
+		Ok(abi.event(event)?)
+	}
+
+	// Finds a non-anonymous event with the given topic0 in any registered ABI.
+	pub fn find_event(&self, topic: H256) -> Option<&Event> {
+		self.contracts
+			.iter()
+			.flat_map(|contract| contract.abi.events())
+			.find(|event| !event.anonymous && event.signature() == topic)
+	}
+
+	// Finds a custom error with the given 4-byte selector in any registered ABI.
+	pub fn find_error(&self, selector: &[u8]) -> Option<&ethabi::AbiError> {
+		self.contracts
+			.iter()
+			.flat_map(|contract| contract.abi.errors())
+			.find(|error| error.signature()[..4] == *selector)
+	}
+
+	pub fn encode_call(&self, contract: &str, function: &str, args: &[Token]) -> Result<Bytes> {
+		let data = self.function(contract, function)?.encode_input(args)?;
+		Ok(Bytes::from(data))
+	}
+
+	pub fn decode_output(&self, contract: &str, function: &str, data: &[u8]) -> Result<Vec<Token>> {
+		Ok(self.function(contract, function)?.decode_output(data)?)
+	}
+}
+
+pub fn input_signature(function: &Function) -> String {
+	let inputs = function.inputs.iter().map(|p| p.kind.to_string()).collect::<Vec<_>>().join(",");
+	format!("{}({})", function.name, inputs)
+}
+
+// Process-wide registry loaded from the environment on first use.
+pub fn registry() -> &'static AbiRegistry {
+	static REGISTRY: OnceLock<AbiRegistry> = OnceLock::new();
+	REGISTRY.get_or_init(AbiRegistry::from_env)
+}
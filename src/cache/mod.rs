@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ethers_core::utils::{hex, keccak256};
+use crate::file_store::JsonFileStore;
+
+// Entries kept when CACHE_CAPACITY is not set.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ttl {
+	None,
+	Seconds(u64),
+	Forever,
+}
+
+impl Ttl {
+	// From a configured number of seconds: 0 disables caching and a negative number caches forever.
+	pub fn from_seconds(seconds: i64) -> Self {
+		match seconds {
+			0 => Ttl::None,
+			s if s < 0 => Ttl::Forever,
+			s => Ttl::Seconds(s as u64),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+	pub value: Value,
+	pub expires_at: Option<u64>,
+}
+
+impl CacheEntry {
+	fn is_fresh(&self, now: u64) -> bool {
+		self.expires_at.is_none_or(|expires_at| now < expires_at)
+	}
+}
+
+pub trait CacheStore: Send + Sync {
+	fn get(&self, key: &str) -> Option<CacheEntry>;
+	fn put(&self, key: &str, entry: CacheEntry);
+}
+
+// At most `capacity` entries, dropping the least recently used one to make room for another.
+struct Lru {
+	capacity: usize,
+	clock: u64,
+	entries: HashMap<String, (CacheEntry, u64)>,
+	order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+	fn new(capacity: usize) -> Self {
+		Lru { capacity: capacity.max(1), clock: 0, entries: HashMap::new(), order: BTreeMap::new() }
+	}
+
+	// The entry of `key`, now the most recently used one. Expired entries are dropped on the way.
+	fn get(&mut self, key: &str) -> Option<CacheEntry> {
+		let (entry, used) = self.entries.get_mut(key)?;
+		self.order.remove(used);
+		if !entry.is_fresh(now()) {
+			self.entries.remove(key);
+			return None;
+		}
+		self.clock += 1;
+		*used = self.clock;
+		self.order.insert(self.clock, key.to_string());
+		Some(entry.clone())
+	}
+
+	fn put(&mut self, key: &str, entry: CacheEntry) {
+		self.clock += 1;
+		if let Some((_, used)) = self.entries.insert(key.to_string(), (entry, self.clock)) {
+			self.order.remove(&used);
+		}
+		self.order.insert(self.clock, key.to_string());
+		while self.entries.len() > self.capacity {
+			let Some((_, oldest)) = self.order.pop_first() else { break };
+			self.entries.remove(&oldest);
+		}
+	}
+}
+
+pub struct MemoryCacheStore {
+	entries: Mutex<Lru>,
+}
+
+impl MemoryCacheStore {
+	pub fn new(capacity: usize) -> Self {
+		MemoryCacheStore { entries: Mutex::new(Lru::new(capacity)) }
+	}
+}
+
+impl CacheStore for MemoryCacheStore {
+	fn get(&self, key: &str) -> Option<CacheEntry> {
+		self.entries.lock().unwrap().get(key)
+	}
+
+	fn put(&self, key: &str, entry: CacheEntry) {
+		self.entries.lock().unwrap().put(key, entry);
+	}
+}
+
+// Keeps the entries in one JSON file as well so they survive across function invocations. The file is read
+// once, when the store is created, and lookups are served from memory; every put is written through to it,
+// oldest entry first, dropping expired entries and the oldest ones beyond the capacity.
+pub struct FileCacheStore {
+	file: JsonFileStore,
+	capacity: usize,
+	memory: MemoryCacheStore,
+}
+
+impl FileCacheStore {
+	pub fn new(path: &str, capacity: usize) -> Self {
+		let file = JsonFileStore::new(path);
+		let memory = MemoryCacheStore::new(capacity);
+		let now = now();
+		match file.read::<Vec<(String, CacheEntry)>>() {
+			Ok(entries) => entries.into_iter().filter(|(_, entry)| entry.is_fresh(now)).for_each(|(key, entry)| memory.put(&key, entry)),
+			Err(err) => log::error!("Failed to load cache {}: {}", path, err),
+		}
+		FileCacheStore { file, capacity, memory }
+	}
+}
+
+impl CacheStore for FileCacheStore {
+	fn get(&self, key: &str) -> Option<CacheEntry> {
+		self.memory.get(key)
+	}
+
+	fn put(&self, key: &str, entry: CacheEntry) {
+		let written = self.file.update(|entries: &mut Vec<(String, CacheEntry)>| {
+			let now = now();
+			entries.retain(|(stored, stored_entry)| stored != key && stored_entry.is_fresh(now));
+			entries.push((key.to_string(), entry.clone()));
+			let excess = entries.len().saturating_sub(self.capacity);
+			entries.drain(..excess);
+		});
+		if let Err(err) = written {
+			log::error!("Failed to write cache {}: {}", self.file.path(), err);
+		}
+		self.memory.put(key, entry);
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct CacheStats {
+	pub hits: u64,
+	pub misses: u64,
+}
+
+pub struct Cache {
+	store: Box<dyn CacheStore>,
+	ttls: HashMap<String, Ttl>,
+	stats: Mutex<HashMap<String, CacheStats>>,
+}
+
+impl Cache {
+	pub fn new(store: Box<dyn CacheStore>) -> Self {
+		Cache { store, ttls: HashMap::new(), stats: Mutex::new(HashMap::new()) }
+	}
+
+	// Overrides the default TTL of the given methods.
+	pub fn with_ttls(mut self, ttls: HashMap<String, Ttl>) -> Self {
+		self.ttls.extend(ttls);
+		self
+	}
+
+	pub fn ttl(&self, method: &str, default: Ttl) -> Ttl {
+		self.ttls.get(method).copied().unwrap_or(default)
+	}
+
+	// Serves `key` from the cache, or runs `fetch` and keeps its result for `ttl` when `cacheable` accepts
+	// it. Errors are never cached. Hits and misses are counted per `method`.
+	pub async fn get_or_fetch<E>(&self, method: &str, key: &str, ttl: Ttl, fetch: impl Future<Output = Result<Value, E>>, cacheable: impl FnOnce(&Value) -> bool) -> Result<Value, E> {
+		if ttl == Ttl::None {
+			return fetch.await;
+		}
+		if let Some(value) = self.get(method, key) {
+			return Ok(value);
+		}
+		let value = fetch.await?;
+		if cacheable(&value) {
+			self.put(key, &value, ttl);
+		}
+		Ok(value)
+	}
+
+	pub fn get(&self, method: &str, key: &str) -> Option<Value> {
+		let value = self.store.get(&store_key(key)).filter(|entry| entry.is_fresh(now())).map(|entry| entry.value);
+		let mut stats = self.stats.lock().unwrap();
+		let stats = stats.entry(method.to_string()).or_default();
+		if value.is_some() {
+			stats.hits += 1;
+		} else {
+			stats.misses += 1;
+		}
+		value
+	}
+
+	pub fn put(&self, key: &str, value: &Value, ttl: Ttl) {
+		let expires_at = match ttl {
+			Ttl::None => return,
+			Ttl::Seconds(seconds) => Some(now() + seconds),
+			Ttl::Forever => None,
+		};
+		self.store.put(&store_key(key), CacheEntry { value: value.clone(), expires_at });
+	}
+
+	pub fn stats(&self) -> HashMap<String, CacheStats> {
+		self.stats.lock().unwrap().clone()
+	}
+}
+
+// Keys are hashed so URLs holding API keys are not written to the store.
+fn store_key(key: &str) -> String {
+	hex::encode(keccak256(key))
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+// Process-wide cache of at most CACHE_CAPACITY entries, kept in the file at CACHE_PATH as well when set.
+// CACHE_TTLS overrides the TTL of methods with a JSON object of seconds, such as {"eth_getBalance": 10, "moralis": 0}.
+pub fn cache() -> &'static Cache {
+	static CACHE: OnceLock<Cache> = OnceLock::new();
+	CACHE.get_or_init(|| {
+		let capacity = std::env::var("CACHE_CAPACITY").ok().and_then(|capacity| capacity.parse().ok()).unwrap_or(DEFAULT_CAPACITY);
+		let store: Box<dyn CacheStore> = match std::env::var("CACHE_PATH") {
+			Ok(path) => Box::new(FileCacheStore::new(&path, capacity)),
+			Err(_) => Box::new(MemoryCacheStore::new(capacity)),
+		};
+		let ttls: HashMap<String, i64> = match std::env::var("CACHE_TTLS") {
+			Ok(config) => serde_json::from_str(&config).unwrap_or_else(|err| {
+				log::error!("Failed to load CACHE_TTLS: {}", err);
+				HashMap::new()
+			}),
+			Err(_) => HashMap::new(),
+		};
+		Cache::new(store).with_ttls(ttls.into_iter().map(|(method, seconds)| (method, Ttl::from_seconds(seconds))).collect())
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn entry(value: u64) -> CacheEntry {
+		CacheEntry { value: json!(value), expires_at: None }
+	}
+
+	fn value(store: &dyn CacheStore, key: &str) -> Option<Value> {
+		store.get(key).map(|entry| entry.value)
+	}
+
+	#[test]
+	fn evicts_least_recently_used_entry() {
+		let store = MemoryCacheStore::new(2);
+		store.put("a", entry(1));
+		store.put("b", entry(2));
+		assert_eq!(value(&store, "a"), Some(json!(1)));
+		store.put("c", entry(3));
+		assert_eq!(value(&store, "b"), None);
+		assert_eq!(value(&store, "a"), Some(json!(1)));
+		assert_eq!(value(&store, "c"), Some(json!(3)));
+	}
+
+	#[test]
+	fn drops_expired_entries() {
+		let store = MemoryCacheStore::new(2);
+		store.put("a", CacheEntry { value: json!(1), expires_at: Some(now() - 1) });
+		assert_eq!(value(&store, "a"), None);
+	}
+
+	#[test]
+	fn file_store_keeps_newest_entries_for_the_next_process() {
+		let path = std::env::temp_dir().join(format!("cache-{}.json", std::process::id())).to_str().unwrap().to_string();
+		let _ = std::fs::remove_file(&path);
+		let store = FileCacheStore::new(&path, 2);
+		for (key, n) in [("a", 1), ("b", 2), ("c", 3)] {
+			store.put(key, entry(n));
+		}
+		let reloaded = FileCacheStore::new(&path, 2);
+		assert_eq!([value(&reloaded, "a"), value(&reloaded, "b"), value(&reloaded, "c")], [None, Some(json!(2)), Some(json!(3))]);
+	}
+}
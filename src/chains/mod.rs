@@ -0,0 +1,190 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use ethers_core::types::Address;
+use crate::abi_registry;
+use crate::indexer_backend::{ExplorerConfig, IndexerBackend};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChainConfig {
+	pub chain_id: u64,
+	#[serde(default)]
+	pub name: Option<String>,
+	// One or more endpoints separated by commas, in order of preference; a list in the config.
+	#[serde(deserialize_with = "deserialize_endpoints")]
+	pub rpc_node_url: String,
+	// History backend; the node itself when not configured.
+	#[serde(default)]
+	pub explorer: Option<ExplorerConfig>,
+	#[serde(default = "default_native_symbol")]
+	pub native_symbol: String,
+	#[serde(default = "default_native_decimals")]
+	pub native_decimals: u32,
+	// Addresses of registered ABIs (such as "pbm") on this chain.
+	#[serde(default)]
+	pub contracts: HashMap<String, Address>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Endpoints {
+	One(String),
+	Many(Vec<String>),
+}
+
+fn deserialize_endpoints<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+	Ok(match Endpoints::deserialize(deserializer)? {
+		Endpoints::One(url) => url,
+		Endpoints::Many(urls) => urls.join(","),
+	})
+}
+
+fn default_native_symbol() -> String {
+	"ETH".to_string()
+}
+
+fn default_native_decimals() -> u32 {
+	18
+}
+
+// The chain of CHAIN_ID (default 18, CyberMiles).
+fn env_chain_id() -> u64 {
+	std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64)
+}
+
+// Addresses the ABI registry was given (CONTRACT_ADDRESS and ABI_REGISTRY), which are those on the chain of CHAIN_ID.
+fn registered_contracts() -> HashMap<String, Address> {
+	abi_registry::registry().contracts().iter().filter_map(|contract| Some((contract.name.clone(), contract.address?))).collect()
+}
+
+impl ChainConfig {
+	// The single chain described by RPC_NODE_URL, CHAIN_ID and the INDEXER_BACKEND variables. Its PBM
+	// contract is the one registered at CONTRACT_ADDRESS.
+	pub fn from_env() -> Self {
+		let chain_id = env_chain_id();
+		let explorer = ExplorerConfig::from_env(chain_id).map_err(|err| log::error!("{}", err)).ok();
+		ChainConfig {
+			chain_id,
+			name: None,
+			rpc_node_url: std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string()),
+			explorer,
+			native_symbol: std::env::var("NATIVE_SYMBOL").unwrap_or(if chain_id == 18 { "CMT" } else { "ETH" }.to_string()),
+			native_decimals: default_native_decimals(),
+			contracts: registered_contracts(),
+		}
+	}
+
+	// Address of the registered contract `name` on this chain.
+	pub fn contract(&self, name: &str) -> Option<Address> {
+		self.contracts.get(name).copied()
+	}
+
+	pub fn backend(&self) -> Box<dyn IndexerBackend> {
+		match &self.explorer {
+			Some(explorer) => explorer.backend(self.chain_id, &self.rpc_node_url),
+			None => ExplorerConfig::Rpc { from_block: None, max_lookback: None, scan_blocks: 1000 }.backend(self.chain_id, &self.rpc_node_url),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct ChainsFile {
+	chains: Vec<ChainConfig>,
+	#[serde(default)]
+	default_chain: Option<u64>,
+}
+
+pub struct ChainRegistry {
+	chains: Vec<ChainConfig>,
+	default_chain: u64,
+}
+
+impl ChainRegistry {
+	pub fn new(chains: Vec<ChainConfig>, default_chain: u64) -> Self {
+		ChainRegistry { chains, default_chain }
+	}
+
+	// Loads the chains from CHAINS, either inline JSON or the path of a .json or .toml file holding a
+	// `chains` list (and optionally `default_chain`). DEFAULT_CHAIN overrides the default, which is
+	// otherwise the first chain. Without CHAINS, the single chain of the legacy variables is served. The
+	// chain of CHAIN_ID takes the registered contract addresses its `contracts` leaves out.
+	pub fn from_env() -> Self {
+		let mut registry = match std::env::var("CHAINS") {
+			Ok(config) => ChainRegistry::from_config(&config).unwrap_or_else(|err| {
+				log::error!("Failed to load CHAINS: {}", err);
+				ChainRegistry::from_chain(ChainConfig::from_env())
+			}),
+			Err(_) => ChainRegistry::from_chain(ChainConfig::from_env()),
+		};
+		let chain_id = env_chain_id();
+		if let Some(chain) = registry.chains.iter_mut().find(|chain| chain.chain_id == chain_id) {
+			for (name, address) in registered_contracts() {
+				chain.contracts.entry(name).or_insert(address);
+			}
+		}
+		if let Some(default_chain) = std::env::var("DEFAULT_CHAIN").ok().and_then(|chain| registry.get(Some(&chain)).ok().map(|c| c.chain_id)) {
+			registry.default_chain = default_chain;
+		}
+		registry
+	}
+
+	fn from_chain(chain: ChainConfig) -> Self {
+		let default_chain = chain.chain_id;
+		ChainRegistry::new(vec![chain], default_chain)
+	}
+
+	pub fn from_config(config: &str) -> Result<Self> {
+		let config = config.trim();
+		let file: ChainsFile = if config.starts_with('[') {
+			ChainsFile { chains: serde_json::from_str(config)?, default_chain: None }
+		} else if config.starts_with('{') {
+			serde_json::from_str(config)?
+		} else if config.ends_with(".toml") {
+			toml::from_str(&std::fs::read_to_string(config)?)?
+		} else {
+			let content = std::fs::read_to_string(config)?;
+			match serde_json::from_str::<Vec<ChainConfig>>(&content) {
+				Ok(chains) => ChainsFile { chains, default_chain: None },
+				Err(_) => serde_json::from_str(&content)?,
+			}
+		};
+		let default_chain = match (file.default_chain, file.chains.first()) {
+			(Some(default_chain), _) => default_chain,
+			(None, Some(first)) => first.chain_id,
+			(None, None) => return Err("CHAINS lists no chain".into()),
+		};
+		Ok(ChainRegistry::new(file.chains, default_chain))
+	}
+
+	pub fn all(&self) -> &[ChainConfig] {
+		&self.chains
+	}
+
+	// Looks a chain up by decimal or 0x hex chain id, or by name. None selects the default chain.
+	pub fn get(&self, chain: Option<&str>) -> Result<&ChainConfig> {
+		let chain = chain.map(str::trim).filter(|chain| !chain.is_empty());
+		let chain_id = match chain {
+			None => Some(self.default_chain),
+			Some(chain) => match chain.strip_prefix("0x") {
+				Some(hex) => u64::from_str_radix(hex, 16).ok(),
+				None => u64::from_str(chain).ok(),
+			},
+		};
+		self.chains
+			.iter()
+			.find(|c| match chain_id {
+				Some(chain_id) => c.chain_id == chain_id,
+				None => c.name.as_deref().is_some_and(|name| chain.is_some_and(|chain| name.eq_ignore_ascii_case(chain))),
+			})
+			.ok_or_else(|| format!("Chain {} is not configured", chain.unwrap_or_default()).into())
+	}
+}
+
+// Process-wide registry loaded from the environment on first use.
+pub fn chains() -> &'static ChainRegistry {
+	static REGISTRY: OnceLock<ChainRegistry> = OnceLock::new();
+	REGISTRY.get_or_init(ChainRegistry::from_env)
+}
@@ -0,0 +1,61 @@
+use serde_json::{json, Value};
+use ethers_core::types::{Address, Log, H256, U256, U64};
+use ethers_core::abi::{Event, LogParam, RawLog, Token};
+use crate::abi_registry;
+use super::{RpcError, RpcResult};
+
+#[derive(Clone, Debug)]
+pub struct DecodedLog {
+	pub event: String,
+	pub params: Vec<LogParam>,
+	pub address: Address,
+	pub transaction_hash: Option<H256>,
+	pub block_number: Option<U64>,
+	pub log_index: Option<U256>,
+}
+
+impl DecodedLog {
+	pub fn param(&self, name: &str) -> Option<&Token> {
+		self.params.iter().find(|param| param.name == name).map(|param| &param.value)
+	}
+
+	pub fn to_json(&self) -> Value {
+		let params = self.params.iter().map(|param| (param.name.clone(), abi_registry::token_to_json(&param.value)));
+		json!({
+			"event": self.event,
+			"params": Value::Object(params.collect()),
+			"address": format!("{:?}", self.address),
+			"transaction_hash": self.transaction_hash,
+			"block_number": self.block_number.map(|n| n.as_u64()),
+			"log_index": self.log_index.map(|n| n.as_u64()),
+		})
+	}
+}
+
+// The topic0 that eth_getLogs entries of `event` carry.
+pub fn event_topic(event: &Event) -> H256 {
+	event.signature()
+}
+
+// Decodes the indexed parameters from the topics and the rest from the data of a log emitted by `event`.
+pub fn decode_log(event: &Event, log: &Log) -> RpcResult<DecodedLog> {
+	if !event.anonymous && log.topics.first() != Some(&event_topic(event)) {
+		return Err(RpcError::Decode(format!("log is not a {} event", event.name)));
+	}
+	let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+	let parsed = event.parse_log(raw).map_err(|err| RpcError::Decode(format!("{} log: {}", event.name, err)))?;
+	Ok(DecodedLog {
+		event: event.name.clone(),
+		params: parsed.params,
+		address: log.address,
+		transaction_hash: log.transaction_hash,
+		block_number: log.block_number,
+		log_index: log.log_index,
+	})
+}
+
+// Decodes a log with whichever registered event matches its topic0.
+pub fn decode_registered_log(log: &Log) -> Option<DecodedLog> {
+	let event = abi_registry::registry().find_event(*log.topics.first()?)?;
+	decode_log(event, log).ok()
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use ethers_core::types::{Address, Log, H256, U256};
+use crate::abi_registry;
+use crate::file_store::JsonFileStore;
+use super::{decode_registered_log, json_rpc, json_rpc_batch, parse_u256, scan_logs, LogCursor, Result, RpcResult};
+
+// Hashes of the blocks scanned within this many blocks of the tip are kept to detect reorgs.
+const REORG_DEPTH: u64 = 64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexedLog {
+	pub event: String,
+	pub params: Map<String, Value>,
+	pub block_number: u64,
+	pub block_hash: H256,
+	pub log_index: u64,
+	pub transaction_hash: H256,
+}
+
+impl IndexedLog {
+	// The log decoded with whichever registered event matches it, if any does.
+	pub fn decode(log: &Log) -> Option<Self> {
+		let decoded = decode_registered_log(log)?;
+		Some(IndexedLog {
+			event: decoded.event,
+			params: decoded.params.iter().map(|param| (param.name.clone(), abi_registry::token_to_json(&param.value))).collect(),
+			block_number: log.block_number?.as_u64(),
+			block_hash: log.block_hash?,
+			log_index: log.log_index.unwrap_or_default().as_u64(),
+			transaction_hash: log.transaction_hash.unwrap_or_default(),
+		})
+	}
+
+	pub fn cursor(&self) -> LogCursor {
+		LogCursor { block_number: self.block_number, log_index: self.log_index }
+	}
+
+	pub fn address_param(&self, name: &str) -> Option<Address> {
+		Address::from_str(self.params.get(name)?.as_str()?).ok()
+	}
+
+	pub fn uint_param(&self, name: &str) -> Option<U256> {
+		U256::from_dec_str(self.params.get(name)?.as_str()?).ok()
+	}
+}
+
+// Decoded events of one contract up to `last_block`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IndexState {
+	pub last_block: Option<u64>,
+	pub block_hashes: BTreeMap<u64, H256>,
+	pub logs: Vec<IndexedLog>,
+}
+
+impl IndexState {
+	// Forgets everything after `block` so it is scanned again.
+	fn rollback(&mut self, block: Option<u64>) {
+		let keep = |number: u64| block.is_some_and(|block| number <= block);
+		self.logs.retain(|log| keep(log.block_number));
+		self.block_hashes.retain(|number, _| keep(*number));
+		self.last_block = block;
+	}
+}
+
+pub trait IndexStore: Send + Sync {
+	fn load(&self, key: &str) -> std::io::Result<Option<IndexState>>;
+	fn save(&self, key: &str, state: &IndexState) -> std::io::Result<()>;
+}
+
+// Keeps the index of every contract in one JSON file so it survives across function invocations.
+pub struct FileIndexStore {
+	file: JsonFileStore,
+}
+
+impl FileIndexStore {
+	pub fn new(path: &str) -> Self {
+		FileIndexStore { file: JsonFileStore::new(path) }
+	}
+}
+
+impl IndexStore for FileIndexStore {
+	fn load(&self, key: &str) -> std::io::Result<Option<IndexState>> {
+		Ok(self.file.read::<HashMap<String, IndexState>>()?.remove(key))
+	}
+
+	fn save(&self, key: &str, state: &IndexState) -> std::io::Result<()> {
+		self.file.update(|states: &mut HashMap<String, IndexState>| {
+			states.insert(key.to_string(), state.clone());
+		})
+	}
+}
+
+pub struct LogIndexer {
+	store: Box<dyn IndexStore>,
+	start_block: u64,
+}
+
+impl LogIndexer {
+	pub fn new(store: Box<dyn IndexStore>, start_block: u64) -> Self {
+		LogIndexer { store, start_block }
+	}
+
+	// Brings the index of `address` on `chain_id` up to the latest block and returns it. Events of every registered ABI
+	// are decoded and kept; logs no registered event matches are skipped.
+	pub async fn sync(&self, rpc_node_url: &str, chain_id: u64, address: Address) -> Result<IndexState> {
+		let contract = format!("{:?}", address);
+		let key = format!("{}:{}", chain_id, contract);
+		let mut state = self.store.load(&key)?.unwrap_or_default();
+
+		if let Some(reorged) = first_reorged_block(rpc_node_url, &state.block_hashes).await? {
+			// A block that still matches vouches for all its ancestors; without one, go back a full reorg depth.
+			let ancestor = state.block_hashes.range(..reorged).next_back().map(|(number, _)| *number).or(reorged.checked_sub(REORG_DEPTH));
+			log::warn!("Reorg of {} detected at block {}, rolling the index back to {:?}", key, reorged, ancestor);
+			state.rollback(ancestor);
+		}
+
+		let latest = json_rpc(rpc_node_url, "eth_getBlockByNumber", json!(["latest", false])).await?;
+		let latest_block = parse_u256(&latest["number"])?.as_u64();
+		let from_block = state.last_block.map_or(self.start_block, |block| (block + 1).max(self.start_block));
+		if from_block <= latest_block {
+			let logs = scan_logs(rpc_node_url, Some(&contract), json!([]), from_block, Some(latest_block)).await?;
+			for log in logs.iter().filter_map(IndexedLog::decode) {
+				state.block_hashes.insert(log.block_number, log.block_hash);
+				state.logs.push(log);
+			}
+			state.block_hashes.insert(latest_block, serde_json::from_value(latest["hash"].clone())?);
+			state.block_hashes.retain(|number, _| number + REORG_DEPTH > latest_block);
+			state.last_block = Some(latest_block);
+			self.store.save(&key, &state)?;
+		}
+		Ok(state)
+	}
+}
+
+// Lowest tracked block whose hash on the node no longer matches the recorded one.
+async fn first_reorged_block(rpc_node_url: &str, block_hashes: &BTreeMap<u64, H256>) -> RpcResult<Option<u64>> {
+	let calls = block_hashes.keys().map(|number| ("eth_getBlockByNumber", json!([format!("{:#x}", number), false]))).collect();
+	let blocks = json_rpc_batch(rpc_node_url, calls).await?;
+	for ((number, hash), block) in block_hashes.iter().zip(blocks) {
+		let current: Option<H256> = block?.get("hash").and_then(|hash| serde_json::from_value(hash.clone()).ok());
+		if current != Some(*hash) {
+			return Ok(Some(*number));
+		}
+	}
+	Ok(None)
+}
+
+// Process-wide indexer, backed by the file at LOG_INDEX_PATH, or None when it is not set as an index that
+// does not outlive the invocation would rescan everything on every request. Scanning starts at
+// LOG_INDEX_START_BLOCK (default 0).
+pub fn log_indexer() -> Option<&'static LogIndexer> {
+	static INDEXER: OnceLock<Option<LogIndexer>> = OnceLock::new();
+	INDEXER
+		.get_or_init(|| {
+			let start_block = std::env::var("LOG_INDEX_START_BLOCK").ok().and_then(|block| block.parse().ok()).unwrap_or(0);
+			let path = std::env::var("LOG_INDEX_PATH").ok()?;
+			Some(LogIndexer::new(Box::new(FileIndexStore::new(&path)), start_block))
+		})
+		.as_ref()
+}
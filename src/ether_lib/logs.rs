@@ -0,0 +1,81 @@
+use serde_json::Value;
+use std::str::FromStr;
+use ethers_core::types::Log;
+use super::{get_block_number, get_log, RpcError, RpcResult};
+
+// Position of a log in the chain, written "block:logIndex", used as the resume point of paginated results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogCursor {
+	pub block_number: u64,
+	pub log_index: u64,
+}
+
+impl FromStr for LogCursor {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		let invalid = || format!("Invalid cursor: {}", s);
+		let (block_number, log_index) = s.split_once(':').ok_or_else(invalid)?;
+		Ok(LogCursor {
+			block_number: block_number.parse().map_err(|_| invalid())?,
+			log_index: log_index.parse().map_err(|_| invalid())?,
+		})
+	}
+}
+
+impl std::fmt::Display for LogCursor {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.block_number, self.log_index)
+	}
+}
+
+// Providers reject eth_getLogs over too wide a range or with too many results using differing messages.
+fn is_range_error(err: &RpcError) -> bool {
+	match err {
+		RpcError::JsonRpc { code, message, .. } => {
+			let message = message.to_lowercase();
+			*code == -32005
+				|| ["too many", "more than", "block range", "range is too", "range too", "limit exceeded", "exceed", "response size"]
+					.iter()
+					.any(|pattern| message.contains(pattern))
+		}
+		_ => false,
+	}
+}
+
+// Largest block range requested in one eth_getLogs call, from LOG_MAX_BLOCK_RANGE. Unset means the
+// whole range is tried first and only split when the node refuses it.
+fn max_block_range() -> u64 {
+	std::env::var("LOG_MAX_BLOCK_RANGE").ok().and_then(|range| range.parse().ok()).filter(|range| *range > 0).unwrap_or(u64::MAX)
+}
+
+// Scans logs between `from_block` and `to_block` (latest when None) in block ranges, halving the range
+// whenever the node rejects it and doubling it again, up to max_block_range, after every range it accepts.
+pub async fn scan_logs(rpc_node_url: &str, address: Option<&str>, topics: Value, from_block: u64, to_block: Option<u64>) -> RpcResult<Vec<Log>> {
+	let to_block = match to_block {
+		Some(to_block) => to_block,
+		None => get_block_number(rpc_node_url).await?,
+	};
+	let mut start = from_block;
+	let max_range = max_block_range();
+	let mut range = max_range;
+	let mut logs: Vec<Log> = vec!();
+
+	while start <= to_block {
+		let end = to_block.min(start.saturating_add(range - 1));
+		match get_log(rpc_node_url, address, topics.clone(), start, end).await {
+			Ok(result) => {
+				let chunk: Vec<Log> = serde_json::from_value(result)?;
+				logs.extend(chunk);
+				start = end + 1;
+				range = range.saturating_mul(2).min(max_range);
+			}
+			Err(err) if end > start && is_range_error(&err) => {
+				range = (end - start).div_ceil(2);
+				log::warn!("eth_getLogs rejected blocks {}..={}, retrying with a range of {} blocks: {}", start, end, range, err);
+			}
+			Err(err) => return Err(err),
+		}
+	}
+	Ok(logs)
+}
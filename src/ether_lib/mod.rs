@@ -2,167 +2,550 @@ use serde_json::Value;
 use serde_json::json;
 use std::collections::HashMap;
 use std::str::FromStr;
-use ethers_signers::{LocalWallet, Signer};
-use ethers_core::types::{NameOrAddress, Bytes, U256, U64, TransactionRequest, transaction::eip2718::TypedTransaction};
-use ethers_core::abi::{Abi, Function, Token};
-use ethers_core::utils::hex;
+use std::time::{Duration, Instant};
+use crate::wallet::TransactionSigner;
+use ethers_core::types::{Address, NameOrAddress, Bytes, U256, U64, Transaction, TransactionReceipt, TransactionRequest, Eip1559TransactionRequest, transaction::eip2718::TypedTransaction};
+use ethers_core::abi::{Function, Token};
+use crate::cache::{cache, Ttl};
+use ethers_core::utils::{hex, keccak256, eip1559_default_estimator, EIP1559_FEE_ESTIMATION_PAST_BLOCKS, EIP1559_FEE_ESTIMATION_REWARD_PERCENTILE};
+
+mod events;
+mod failover;
+mod indexer;
+mod logs;
+mod nonce;
+mod revert;
+pub use events::*;
+pub use failover::*;
+pub use indexer::*;
+pub use logs::*;
+pub use nonce::*;
+pub use revert::*;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+pub type RpcResult<T> = std::result::Result<T, RpcError>;
 
-pub fn create_contract_call_data(name: &str, tokens: Vec<Token>) -> Result<Bytes> {
-    
-    let contract_abi: &str = r#"[
-		{
-			"inputs": [
-				{"internalType": "address", "name": "receiver", "type": "address"},
-				{"internalType": "uint256", "name": "amount", "type": "uint256"}
-			],
-			"name": "pay",
-			"outputs": [],
-			"stateMutability": "nonpayable",
-			"type": "function"
-		},
-		{
-			"inputs": [
-				{
-					"internalType": "address",
-					"name": "user",
-					"type": "address"
-				}
-			],
-			"name": "balanceOf",
-			"outputs": [
-				{
-					"internalType": "uint256",
-					"name": "",
-					"type": "uint256"
-				}
-			],
-			"stateMutability": "view",
-			"type": "function"
+#[derive(Debug)]
+pub enum RpcError {
+	Transport(reqwest::Error),
+	HttpStatus(u16, String),
+	JsonRpc { code: i64, message: String, data: Option<Value> },
+	MissingResult,
+	Decode(String),
+	NoEndpoint,
+}
+
+impl RpcError {
+	fn from_error_object(error: &Value) -> Self {
+		RpcError::JsonRpc {
+			code: error["code"].as_i64().unwrap_or_default(),
+			message: error["message"].as_str().unwrap_or_default().to_string(),
+			data: error.get("data").filter(|data| !data.is_null()).cloned(),
 		}
-	]"#;
-    let abi: Abi = serde_json::from_str(&contract_abi).unwrap();
-    let function: &Function = abi
-        .functions()
-        .find(|&f| f.name == name)
-        .ok_or("Function not found in ABI")?;
-
-    let data = function.encode_input(&tokens).unwrap();
-
-    Ok(Bytes::from(data))
-}
-
-pub async fn wrap_transaction(rpc_node_url: &str, chain_id: u64, wallet: LocalWallet, address_to: NameOrAddress, data: Bytes, value: U256) -> Result<String> {
-	let address_from = wallet.address();
-	let nonce = get_nonce(&rpc_node_url, format!("{:?}", wallet.address()).as_str()).await.unwrap();
-	let estimate_gas = get_estimate_gas(&rpc_node_url, format!("{:?}", address_from).as_str(), 
-										format!("{:?}", address_to.as_address().expect("Failed to transfer address")).as_str(), 
-										format!("0x{:x}", value).as_str(), format!("{:}", data).as_str())
-										.await
-										.expect("Failed to gat estimate gas.") * U256::from(12) / U256::from(10);
-	
-	let tx: TypedTransaction = TransactionRequest::new()
-	.from(address_from)
-	.to(address_to) 
-	.nonce::<U256>(nonce.into())
-	.gas_price::<U256>(get_gas_price(&rpc_node_url).await.expect("Failed to get gas price.").into())
-	.gas::<U256>(estimate_gas.into())
-	.chain_id::<U64>(chain_id.into())
-	.data::<Bytes>(data.into())
-	.value(value).into();    
-	
+	}
+}
+
+impl std::fmt::Display for RpcError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RpcError::Transport(err) => write!(f, "transport error: {}", err),
+			RpcError::HttpStatus(status, body) => write!(f, "node returned HTTP {}: {}", status, body),
+			RpcError::JsonRpc { code, message, .. } => write!(f, "JSON-RPC error {}: {}", code, message),
+			RpcError::MissingResult => write!(f, "response has neither result nor error"),
+			RpcError::Decode(msg) => write!(f, "failed to decode response: {}", msg),
+			RpcError::NoEndpoint => write!(f, "no RPC endpoint configured"),
+		}
+	}
+}
+
+impl std::error::Error for RpcError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			RpcError::Transport(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<reqwest::Error> for RpcError {
+	fn from(err: reqwest::Error) -> Self {
+		RpcError::Transport(err)
+	}
+}
+
+impl From<serde_json::Error> for RpcError {
+	fn from(err: serde_json::Error) -> Self {
+		RpcError::Decode(err.to_string())
+	}
+}
+
+fn parse_u256(value: &Value) -> RpcResult<U256> {
+	let quantity = value.as_str().ok_or_else(|| RpcError::Decode(format!("expected hex quantity, got {}", value)))?;
+	U256::from_str(quantity).map_err(|err| RpcError::Decode(format!("{}: {}", quantity, err)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxType {
+	Legacy,
+	Eip1559,
+}
+
+impl FromStr for TxType {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"legacy" | "0" => Ok(TxType::Legacy),
+			"1559" | "eip1559" | "2" => Ok(TxType::Eip1559),
+			other => Err(format!("Unknown transaction type: {}", other)),
+		}
+	}
+}
+
+// Returns (max_fee_per_gas, max_priority_fee_per_gas) from the latest base fee and an eth_feeHistory result.
+pub fn estimate_eip1559_fees(base_fee: U256, fee_history: &Value) -> RpcResult<(U256, U256)> {
+	let mut rewards: Vec<Vec<U256>> = vec!();
+	for block in fee_history["reward"].as_array().unwrap_or(&vec!()) {
+		let block_rewards = block.as_array().unwrap_or(&vec!()).iter().map(parse_u256).collect::<RpcResult<Vec<U256>>>()?;
+		if !block_rewards.is_empty() {
+			rewards.push(block_rewards);
+		}
+	}
+	Ok(eip1559_default_estimator(base_fee, rewards))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Fees {
+	Legacy { gas_price: U256 },
+	Eip1559 { max_fee_per_gas: U256, max_priority_fee_per_gas: U256 },
+}
+
+fn fee_calls() -> Vec<(&'static str, Value)> {
+	vec![
+		("eth_gasPrice", json!([])),
+		("eth_getBlockByNumber", json!(["latest", false])),
+		("eth_feeHistory", json!([format!("{:#x}", EIP1559_FEE_ESTIMATION_PAST_BLOCKS), "latest", [EIP1559_FEE_ESTIMATION_REWARD_PERCENTILE]])),
+	]
+}
+
+// Picks the fee model from the results of `fee_calls`. Without an explicit type, EIP-1559 is used
+// whenever the latest block carries a base fee.
+fn select_fees(tx_type: Option<TxType>, gas_price: RpcResult<Value>, latest_block: RpcResult<Value>, fee_history: RpcResult<Value>) -> Result<Fees> {
+	let base_fee = latest_block.ok().and_then(|block| parse_u256(&block["baseFeePerGas"]).ok());
+	match (tx_type, base_fee) {
+		(Some(TxType::Legacy), _) | (None, None) => Ok(Fees::Legacy { gas_price: parse_u256(&gas_price?)? }),
+		(Some(TxType::Eip1559), None) => Err("Chain does not support EIP-1559 transactions".into()),
+		(_, Some(base_fee)) => {
+			let fee_history = fee_history.unwrap_or_else(|err| {
+				log::warn!("eth_feeHistory unavailable, using default priority fee: {}", err);
+				Value::Null
+			});
+			let (max_fee_per_gas, max_priority_fee_per_gas) = estimate_eip1559_fees(base_fee, &fee_history)?;
+			Ok(Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas })
+		}
+	}
+}
+
+fn build_transaction(fees: Fees, address_from: Address, address_to: NameOrAddress, gas: U256, chain_id: u64, data: Bytes, value: U256) -> TypedTransaction {
+	match fees {
+		Fees::Legacy { gas_price } => TransactionRequest::new()
+			.from(address_from)
+			.to(address_to) 
+			.gas_price::<U256>(gas_price)
+			.gas::<U256>(gas)
+			.chain_id::<U64>(chain_id.into())
+			.data::<Bytes>(data)
+			.value(value).into(),
+		Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas } => Eip1559TransactionRequest::new()
+			.from(address_from)
+			.to(address_to)
+			.max_fee_per_gas::<U256>(max_fee_per_gas)
+			.max_priority_fee_per_gas::<U256>(max_priority_fee_per_gas)
+			.gas::<U256>(gas)
+			.chain_id::<U64>(chain_id.into())
+			.data::<Bytes>(data)
+			.value(value).into(),
+	}
+}
+
+async fn sign_raw_transaction(signer: &dyn TransactionSigner, tx: &TypedTransaction) -> Result<String> {
 	log::info!("Tx: {:#?}", tx); 
 	
-	let signature = wallet.sign_transaction(&tx).await.expect("Failed to sign.");
+	let signed = signer.sign_transaction(tx).await?;
 	
 
-	Ok(format!("0x{}", hex::encode(tx.rlp_signed(&signature))))
+	Ok(format!("0x{}", hex::encode(signed)))
+}
+
+pub async fn wrap_transaction(rpc_node_url: &str, chain_id: u64, signer: &dyn TransactionSigner, address_to: NameOrAddress, data: Bytes, value: U256, tx_type: Option<TxType>) -> Result<String> {
+	let address_from = signer.address();
+	let address_to_str = format!("{:?}", address_to.as_address().ok_or("Failed to transfer address")?);
+	let mut calls = vec![
+		("eth_getTransactionCount", json!([format!("{:?}", address_from), "pending"])),
+		("eth_estimateGas", json!([{"from": format!("{:?}", address_from), "to": address_to_str, "value": format!("0x{:x}", value), "data": format!("{:}", data)}])),
+	];
+	calls.extend(fee_calls());
+	let results = json_rpc_batch(rpc_node_url, calls).await?;
+	let [nonce, estimate_gas, gas_price, latest_block, fee_history]: [RpcResult<Value>; 5] = results.try_into().map_err(|_| RpcError::MissingResult)?;
+	let estimate_gas = parse_u256(&estimate_gas?)? * U256::from(12) / U256::from(10);
+	let fees = select_fees(tx_type, gas_price, latest_block, fee_history)?;
+
+	let mut tx = build_transaction(fees, address_from, address_to, estimate_gas, chain_id, data, value);
+	tx.set_nonce(nonce_manager().next_nonce(chain_id, address_from, parse_u256(&nonce?)?)?);
+
+	// A nonce that is never broadcast would hold back every later one, so hand it out again.
+	let raw_tx = sign_raw_transaction(signer, &tx).await;
+	if raw_tx.is_err() {
+		nonce_manager().resync(chain_id, address_from);
+	}
+	raw_tx
+}
+
+// Geth and most other nodes reject a replacement unless every fee field is raised by at least 10%.
+const MIN_REPLACEMENT_BUMP_PERCENT: u64 = 10;
+
+fn bump_fee(original: U256, market: U256) -> U256 {
+	std::cmp::max(original * (100 + MIN_REPLACEMENT_BUMP_PERCENT) / 100, market)
+}
+
+// Re-signs a pending transaction with the same nonce and bumped fees, or, with `cancel`, replaces it
+// by a zero-value transfer to the sender. Returns the hash of the replacement.
+pub async fn replace_transaction(rpc_node_url: &str, chain_id: u64, signer: &dyn TransactionSigner, original: &Transaction, cancel: bool) -> Result<String> {
+	let results = json_rpc_batch(rpc_node_url, fee_calls()).await?;
+	let [gas_price, latest_block, fee_history]: [RpcResult<Value>; 3] = results.try_into().map_err(|_| RpcError::MissingResult)?;
+	let tx_type = if original.transaction_type == Some(2.into()) { TxType::Eip1559 } else { TxType::Legacy };
+	let fees = match select_fees(Some(tx_type), gas_price, latest_block, fee_history)? {
+		Fees::Legacy { gas_price } => Fees::Legacy {
+			gas_price: bump_fee(original.gas_price.unwrap_or_default(), gas_price),
+		},
+		Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas } => {
+			let max_priority_fee_per_gas = bump_fee(original.max_priority_fee_per_gas.unwrap_or_default(), max_priority_fee_per_gas);
+			Fees::Eip1559 {
+				max_fee_per_gas: std::cmp::max(bump_fee(original.max_fee_per_gas.unwrap_or_default(), max_fee_per_gas), max_priority_fee_per_gas),
+				max_priority_fee_per_gas,
+			}
+		}
+	};
+
+	let address_from = signer.address();
+	let mut tx = if cancel {
+		build_transaction(fees, address_from, address_from.into(), U256::from(21000), chain_id, Bytes::default(), U256::zero())
+	} else {
+		let address_to = original.to.ok_or("Cannot replace a contract creation")?;
+		build_transaction(fees, address_from, address_to.into(), original.gas, chain_id, original.input.clone(), original.value)
+	};
+	tx.set_nonce(original.nonce);
+
+	let raw_tx = sign_raw_transaction(signer, &tx).await?;
+	Ok(send_raw_transaction(rpc_node_url, &raw_tx).await?)
+}
+
+// Submits a signed transaction. Failover only ever rebroadcasts these same bytes, which cannot be mined
+// twice, and a node that already has the transaction ("already known", or "nonce too low" once it is
+// mined) counts as success. Returns the transaction hash.
+pub async fn send_raw_transaction(rpc_node_url: &str, raw_tx: &str) -> RpcResult<String> {
+	let bytes = hex::decode(raw_tx.trim_start_matches("0x")).map_err(|err| RpcError::Decode(err.to_string()))?;
+	let hash = format!("0x{}", hex::encode(keccak256(bytes)));
+	match json_rpc(rpc_node_url, "eth_sendRawTransaction", json!([raw_tx])).await {
+		Ok(result) => Ok(result.as_str().map(str::to_string).unwrap_or(hash)),
+		Err(err) if is_already_known(&err) => {
+			log::info!("Transaction {} is already known to the node", hash);
+			Ok(hash)
+		}
+		Err(err) if is_nonce_too_low(&err) => match eth_get_tx_by_hash(rpc_node_url, &hash).await {
+			Ok(tx) if !tx.is_null() => Ok(hash),
+			_ => Err(err),
+		},
+		Err(err) => Err(err),
+	}
+}
+
+fn is_already_known(err: &RpcError) -> bool {
+	matches!(err, RpcError::JsonRpc { message, .. } if {
+		let message = message.to_lowercase();
+		message.contains("already known") || message.contains("known transaction") || message.contains("already imported")
+	})
+}
+
+// Signs and submits a transaction, resynchronising the nonce manager when the node rejects it
+// and retrying once if the rejection was "nonce too low". Returns the transaction hash.
+pub async fn sign_and_send_transaction(rpc_node_url: &str, chain_id: u64, signer: &dyn TransactionSigner, address_to: NameOrAddress, data: Bytes, value: U256, tx_type: Option<TxType>) -> Result<String> {
+	let address_from = signer.address();
+	let mut attempts = 0;
+	loop {
+		let raw_tx = wrap_transaction(rpc_node_url, chain_id, signer, address_to.clone(), data.clone(), value, tx_type).await?;
+		match send_raw_transaction(rpc_node_url, &raw_tx).await {
+			Ok(hash) => return Ok(hash),
+			Err(err) => {
+				nonce_manager().resync(chain_id, address_from);
+				attempts += 1;
+				if attempts > 1 || !is_nonce_too_low(&err) {
+					return Err(err.into());
+				}
+				log::warn!("Nonce too low for {:?}, resyncing with the node and retrying", address_from);
+			}
+		}
+	}
+}
+
+fn is_nonce_too_low(err: &RpcError) -> bool {
+	matches!(err, RpcError::JsonRpc { message, .. } if message.to_lowercase().contains("nonce too low"))
 }
 
-pub async fn eth_call(rpc_node_url: &str, from: &str, to: &str, data: &str) -> Result<String> {
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Polls eth_getTransactionReceipt until the transaction has `confirmations` blocks on top of it
+// (1 means mined). Returns None if that does not happen within `timeout`.
+pub async fn wait_for_receipt(rpc_node_url: &str, hash: &str, confirmations: u64, timeout: Duration) -> RpcResult<Option<TransactionReceipt>> {
+	let started = Instant::now();
+	loop {
+		let results = json_rpc_batch(rpc_node_url, vec![
+			("eth_getTransactionReceipt", json!([hash])),
+			("eth_blockNumber", json!([])),
+		]).await?;
+		let [receipt, block_number]: [RpcResult<Value>; 2] = results.try_into().map_err(|_| RpcError::MissingResult)?;
+		let receipt = receipt?;
+		if !receipt.is_null() {
+			let receipt: TransactionReceipt = serde_json::from_value(receipt)?;
+			let latest = parse_u256(&block_number?)?.as_u64();
+			let mined_at = receipt.block_number.unwrap_or_default().as_u64();
+			if latest + 1 >= mined_at + confirmations {
+				return Ok(Some(receipt));
+			}
+		}
+		if started.elapsed() + RECEIPT_POLL_INTERVAL > timeout {
+			return Ok(None);
+		}
+		tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+	}
+}
+
+pub async fn eth_call(rpc_node_url: &str, from: &str, to: &str, data: &str) -> RpcResult<String> {
 	let params = json!([{"from": from, "to": to, "data": data}, "latest"]);
-	let result = json_rpc(rpc_node_url, "eth_call", params).await.expect("Failed to send json.");
+	let result = json_rpc(rpc_node_url, "eth_call", params).await?;
+	result.as_str().map(String::from).ok_or_else(|| RpcError::Decode(format!("expected hex data, got {}", result)))
+}
 
-	Ok(result)
+// Encodes `args` for `function`, runs eth_call and decodes the returned data through the function's outputs.
+pub async fn eth_call_function(rpc_node_url: &str, from: &str, to: &str, function: &Function, args: &[Token]) -> Result<Vec<Token>> {
+	let data = Bytes::from(function.encode_input(args)?);
+	let result = eth_call(rpc_node_url, from, to, format!("{:}", data).as_str()).await?;
+	let output = hex::decode(result.trim_start_matches("0x")).map_err(|err| RpcError::Decode(err.to_string()))?;
+	let tokens = function.decode_output(&output).map_err(|err| RpcError::Decode(format!("{} output: {}", function.name, err)))?;
+	Ok(tokens)
 }
 
-pub async fn eth_get_block_by_hash(rpc_node_url: &str, hash: &str) -> Result<Value>{
+pub async fn eth_get_block_by_hash(rpc_node_url: &str, hash: &str) -> RpcResult<Value>{
 	let params = json!([hash, false]);
-	let result = json_rpc(rpc_node_url, "eth_getBlockByHash", params).await.expect("Failed to send json.");
-	Ok(serde_json::from_str(&result).unwrap())
+	json_rpc(rpc_node_url, "eth_getBlockByHash", params).await
 }
 
-pub async fn eth_get_tx_by_hash(rpc_node_url: &str, hash: &str) -> Result<Value>{
+pub async fn eth_get_tx_by_hash(rpc_node_url: &str, hash: &str) -> RpcResult<Value>{
 	let params = json!([hash]);
-	let result = json_rpc(rpc_node_url, "eth_getTransactionByHash", params).await.expect("Failed to send json.");
-	Ok(serde_json::from_str(&result).unwrap())
+	json_rpc(rpc_node_url, "eth_getTransactionByHash", params).await
+}
+
+pub async fn eth_get_txs_by_hash(rpc_node_url: &str, hashes: &[&str]) -> RpcResult<Vec<Value>>{
+	let calls = hashes.iter().map(|hash| ("eth_getTransactionByHash", json!([hash]))).collect();
+	json_rpc_batch(rpc_node_url, calls).await?.into_iter().collect()
 }
 
-pub async fn get_ethbalance(rpc_node_url: &str, address: &str) -> Result<U256> {
+pub async fn get_ethbalance(rpc_node_url: &str, address: &str) -> RpcResult<U256> {
 	let params = json!([address, "latest"]);
-	let result = json_rpc(rpc_node_url, "eth_getBalance", params).await.expect("Failed to send json.");
-	Ok(U256::from_str(&result)?)
+	parse_u256(&json_rpc(rpc_node_url, "eth_getBalance", params).await?)
 }
 
-pub async fn get_gas_price(rpc_node_url: &str) -> Result<U256> {
+pub async fn get_gas_price(rpc_node_url: &str) -> RpcResult<U256> {
 	let params = json!([]);
-	let result = json_rpc(rpc_node_url, "eth_gasPrice", params).await.expect("Failed to send json.");
-	
-	Ok(U256::from_str(&result)?)
+	parse_u256(&json_rpc(rpc_node_url, "eth_gasPrice", params).await?)
 }
 
-pub async fn get_nonce(rpc_node_url: &str, address: &str) -> Result<U256> {
+pub async fn get_nonce(rpc_node_url: &str, address: &str) -> RpcResult<U256> {
 	let params = json!([address, "pending"]);
-	let result = json_rpc(rpc_node_url, "eth_getTransactionCount", params).await.expect("Failed to send json.");
-	
-	Ok(U256::from_str(&result)?)
+	parse_u256(&json_rpc(rpc_node_url, "eth_getTransactionCount", params).await?)
 }
 
-pub async fn get_estimate_gas(rpc_node_url: &str, from: &str, to: &str, value: &str, data: &str) -> Result<U256> {
+pub async fn get_estimate_gas(rpc_node_url: &str, from: &str, to: &str, value: &str, data: &str) -> RpcResult<U256> {
 	let params = json!([{"from": from, "to": to, "value":value, "data":data}]);
-	let result = json_rpc(rpc_node_url, "eth_estimateGas", params).await.expect("Failed to send json.");
-	
-	Ok(U256::from_str(&result)?)
+	parse_u256(&json_rpc(rpc_node_url, "eth_estimateGas", params).await?)
 }
 
-pub async fn get_log(rpc_node_url: &str, address: &str, topic: Value) -> Result<Value>{
-	let params = json!([{"address": address, "fromBlock": "earliest", "topics":topic}]);
-	let result = json_rpc(rpc_node_url, "eth_getLogs", params).await.expect("Failed to send json.");
-	Ok(serde_json::from_str(&result).unwrap())
+pub async fn get_block_number(rpc_node_url: &str) -> RpcResult<u64> {
+	let params = json!([]);
+	Ok(parse_u256(&json_rpc(rpc_node_url, "eth_blockNumber", params).await?)?.as_u64())
 }
 
-pub async fn json_rpc(url: &str, method: &str, params: Value) -> Result<String> {
-	let client = reqwest::Client::new();
-	let res = client
-		.post(url)
-		.header("Content-Type","application/json")
-		.body(json!({
+// Logs of the contract at `address`, or of every contract when None.
+pub async fn get_log(rpc_node_url: &str, address: Option<&str>, topic: Value, from_block: u64, to_block: u64) -> RpcResult<Value>{
+	let mut filter = json!({"fromBlock": format!("{:#x}", from_block), "toBlock": format!("{:#x}", to_block), "topics":topic});
+	if let Some(address) = address {
+		filter["address"] = json!(address);
+	}
+	let params = json!([filter]);
+	json_rpc(rpc_node_url, "eth_getLogs", params).await
+}
+
+// How long successful results of `method` are cached unless CACHE_TTLS says otherwise. Mined
+// transactions, receipts and blocks by hash never change, balances and calls are cached briefly, and
+// everything else (nonces, gas, block numbers, logs, ...) is always read from the node.
+fn rpc_cache_ttl(method: &str) -> Ttl {
+	let default = match method {
+		"eth_chainId" | "eth_getTransactionByHash" | "eth_getTransactionReceipt" | "eth_getBlockByHash" => Ttl::Forever,
+		"eth_getBalance" | "eth_call" => Ttl::Seconds(5),
+		_ => Ttl::None,
+	};
+	cache().ttl(method, default)
+}
+
+// Pending transactions and unknown hashes come back without a block number and are not cached.
+fn rpc_result_cacheable(method: &str, result: &Value) -> bool {
+	match method {
+		"eth_getTransactionByHash" | "eth_getTransactionReceipt" => !result["blockNumber"].is_null(),
+		_ => !result.is_null(),
+	}
+}
+
+fn rpc_cache_key(url: &str, method: &str, params: &Value) -> String {
+	format!("{} {} {}", url, method, params)
+}
+
+pub async fn json_rpc(url: &str, method: &str, params: Value) -> RpcResult<Value> {
+	let key = rpc_cache_key(url, method, &params);
+	cache().get_or_fetch(method, &key, rpc_cache_ttl(method), send_json_rpc(url, method, params), |result| rpc_result_cacheable(method, result)).await
+}
+
+// One JSON-RPC call to `url` alone, without the cache or failover, for services such as a remote signer
+// that must see every request exactly once.
+pub async fn json_rpc_direct(url: &str, method: &str, params: Value) -> RpcResult<Value> {
+	let request = json!({
+		"jsonrpc": "2.0",
+		"method": method,
+		"params": params,
+		"id": 1
+	});
+	parse_response(post_json_to(url, &request).await?)
+}
+
+async fn send_json_rpc(url: &str, method: &str, params: Value) -> RpcResult<Value> {
+	let request = json!({
+		"jsonrpc": "2.0",
+		"method": method,
+		"params": params,
+		"id": 1
+	});
+	let response = post_json(url, &request).await?;
+	let result = parse_response(response);
+	if let Err(err) = &result {
+		log::error!("{} request body: {:#?}", method, request);
+		log::error!("{} response error: {}", method, err);
+	}
+	result
+}
+
+// Like json_rpc for every call, in one request for the calls the cache cannot answer.
+pub async fn json_rpc_batch(url: &str, calls: Vec<(&str, Value)>) -> RpcResult<Vec<RpcResult<Value>>> {
+	let ttls: Vec<Ttl> = calls.iter().map(|(method, _)| rpc_cache_ttl(method)).collect();
+	let mut results: Vec<Option<RpcResult<Value>>> = calls
+		.iter()
+		.zip(&ttls)
+		.map(|((method, params), ttl)| (*ttl != Ttl::None).then(|| cache().get(method, &rpc_cache_key(url, method, params))).flatten().map(Ok))
+		.collect();
+	let missing: Vec<usize> = (0..calls.len()).filter(|idx| results[*idx].is_none()).collect();
+	let fetched = send_json_rpc_batch(url, missing.iter().map(|idx| calls[*idx].clone()).collect()).await?;
+	for (idx, result) in missing.into_iter().zip(fetched) {
+		let (method, params) = &calls[idx];
+		if let Ok(value) = &result {
+			if rpc_result_cacheable(method, value) {
+				cache().put(&rpc_cache_key(url, method, params), value, ttls[idx]);
+			}
+		}
+		results[idx] = Some(result);
+	}
+	Ok(results.into_iter().map(|result| result.unwrap_or(Err(RpcError::MissingResult))).collect())
+}
+
+async fn send_json_rpc_batch(url: &str, calls: Vec<(&str, Value)>) -> RpcResult<Vec<RpcResult<Value>>> {
+	if calls.is_empty() {
+		return Ok(vec![]);
+	}
+	let request: Vec<Value> = calls
+		.iter()
+		.enumerate()
+		.map(|(id, (method, params))| json!({
 			"jsonrpc": "2.0",
 			"method": method,
 			"params": params,
-			"id": 1
-		}).to_string())
+			"id": id
+		}))
+		.collect();
+	let response = post_json(url, &Value::Array(request)).await?;
+	let responses = match response {
+		Value::Array(responses) => responses,
+		other => return Err(parse_response(other).err().unwrap_or(RpcError::Decode("expected a batch response array".to_string()))),
+	};
+
+	let mut by_id: HashMap<u64, Value> = HashMap::new();
+	for response in responses {
+		if let Some(id) = response["id"].as_u64() {
+			by_id.insert(id, response);
+		}
+	}
+	Ok(calls
+		.iter()
+		.enumerate()
+		.map(|(id, (method, _))| {
+			let result = by_id.remove(&(id as u64)).ok_or(RpcError::MissingResult).and_then(parse_response);
+			if let Err(err) = &result {
+				log::error!("{} batch response error: {}", method, err);
+			}
+			result
+		})
+		.collect())
+}
+
+fn parse_response(mut response: Value) -> RpcResult<Value> {
+	match response.get_mut("error").map(Value::take) {
+		Some(error) if !error.is_null() => Err(RpcError::from_error_object(&error)),
+		_ => response.get_mut("result").map(Value::take).ok_or(RpcError::MissingResult),
+	}
+}
+
+// Posts to the endpoints of `url` in turn, see with_failover. A rate-limit error in the response moves
+// on to the next endpoint too.
+async fn post_json(url: &str, request: &Value) -> RpcResult<Value> {
+	with_failover(url, |endpoint| async move {
+		let response = post_json_to(&endpoint, request).await?;
+		match response.get("error").filter(|error| !error.is_null()).map(RpcError::from_error_object) {
+			Some(err) if is_retryable(&err) => Err(err),
+			_ => Ok(response),
+		}
+	}).await
+}
+
+// HTTP client whose requests give up after HTTP_TIMEOUT_SECS (default 30), so a hung endpoint fails like an
+// unreachable one and the next endpoint is tried. Built per request as its connections belong to the runtime
+// of the handler that opened them.
+pub fn http_client() -> reqwest::Client {
+	let timeout = std::env::var("HTTP_TIMEOUT_SECS").ok().and_then(|seconds| seconds.parse().ok()).unwrap_or(30);
+	reqwest::Client::builder().timeout(Duration::from_secs(timeout)).build().expect("Failed to build the HTTP client")
+}
+
+async fn post_json_to(url: &str, request: &Value) -> RpcResult<Value> {
+	let res = http_client()
+		.post(url)
+		.header("Content-Type","application/json")
+		.body(request.to_string())
 		.send()
-		.await?;
+		.await
+		// Node providers often put the API key in the URL.
+		.map_err(reqwest::Error::without_url)?;
 
-	let body = res.text().await?;
-	let map: HashMap<String, serde_json::Value> = serde_json::from_str(body.as_str())?;
-	if !map.contains_key("result"){
-		log::error!("{} request body: {:#?}", method, json!({
-			"jsonrpc": "2.0",
-			"method": method,
-			"params": params,
-			"id": 1
-		}));
-		log::error!("{} response body: {:#?}", method, map);
-		println!("{} request body: {:#?}", method, json!({
-			"jsonrpc": "2.0",
-			"method": method,
-			"params": params,
-			"id": 1
-		}));
-		println!("{} response body: {:#?}", method, map);
+	let status = res.status();
+	let body = res.text().await.map_err(reqwest::Error::without_url)?;
+	if !status.is_success() {
+		log::error!("request body: {:#?}", request);
+		log::error!("HTTP {} response body: {}", status, body);
+		return Err(RpcError::HttpStatus(status.as_u16(), body));
 	}
-	Ok(serde_json::to_string(&map["result"]).expect("Failed to parse str.").trim_matches('"').to_string())
-}
\ No newline at end of file
+	Ok(serde_json::from_str(body.as_str())?)
+}
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ethers_core::types::{Address, U256};
+use crate::file_store::JsonFileStore;
+
+// A stored counter ahead of a node pending count that has not advanced for longer than this is treated
+// as a gap (the transactions holding those nonces never reached the node) and is resynchronised.
+const NONCE_GAP_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NonceRecord {
+	pub next_nonce: U256,
+	// The node's pending transaction count when last seen, and when it last advanced.
+	#[serde(default)]
+	pub node_pending: U256,
+	#[serde(default)]
+	pub pending_since: u64,
+}
+
+pub trait NonceStore: Send + Sync {
+	// Replaces the record of (chain_id, address) by `update` of it, with no other update of the store in
+	// between, or removes it when `update` returns None.
+	fn update(&self, chain_id: u64, address: Address, update: &mut dyn FnMut(Option<NonceRecord>) -> Option<NonceRecord>) -> std::io::Result<()>;
+}
+
+fn store_key(chain_id: u64, address: Address) -> String {
+	format!("{}:{:?}", chain_id, address)
+}
+
+#[derive(Default)]
+pub struct MemoryNonceStore {
+	records: Mutex<HashMap<String, NonceRecord>>,
+}
+
+impl NonceStore for MemoryNonceStore {
+	fn update(&self, chain_id: u64, address: Address, update: &mut dyn FnMut(Option<NonceRecord>) -> Option<NonceRecord>) -> std::io::Result<()> {
+		let mut records = self.records.lock().unwrap();
+		let key = store_key(chain_id, address);
+		match update(records.remove(&key)) {
+			Some(record) => records.insert(key, record),
+			None => None,
+		};
+		Ok(())
+	}
+}
+
+// Keeps every counter in one JSON file so they survive across function invocations.
+pub struct FileNonceStore {
+	file: JsonFileStore,
+}
+
+impl FileNonceStore {
+	pub fn new(path: &str) -> Self {
+		FileNonceStore { file: JsonFileStore::new(path) }
+	}
+}
+
+impl NonceStore for FileNonceStore {
+	fn update(&self, chain_id: u64, address: Address, update: &mut dyn FnMut(Option<NonceRecord>) -> Option<NonceRecord>) -> std::io::Result<()> {
+		self.file.update(|records: &mut HashMap<String, NonceRecord>| {
+			let key = store_key(chain_id, address);
+			if let Some(record) = update(records.remove(&key)) {
+				records.insert(key, record);
+			}
+		})
+	}
+}
+
+pub struct NonceManager {
+	store: Box<dyn NonceStore>,
+}
+
+impl NonceManager {
+	pub fn new(store: Box<dyn NonceStore>) -> Self {
+		NonceManager { store }
+	}
+
+	// Hands out the next nonce for (chain_id, address) given the node's pending transaction count.
+	pub fn next_nonce(&self, chain_id: u64, address: Address, pending: U256) -> std::io::Result<U256> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+		self.next_nonce_at(chain_id, address, pending, now)
+	}
+
+	fn next_nonce_at(&self, chain_id: u64, address: Address, pending: U256, now: u64) -> std::io::Result<U256> {
+		let mut nonce = pending;
+		self.store.update(chain_id, address, &mut |record| {
+			let pending_since = match &record {
+				Some(record) if pending <= record.node_pending => record.pending_since,
+				_ => now,
+			};
+			let (next, pending_since) = match record {
+				Some(record) if record.next_nonce > pending && now.saturating_sub(pending_since) > NONCE_GAP_TIMEOUT_SECS => {
+					log::warn!("Nonce gap for {:?} on chain {}: stored {}, node pending {} since {}", address, chain_id, record.next_nonce, pending, pending_since);
+					(pending, now)
+				}
+				Some(record) if record.next_nonce > pending => (record.next_nonce, pending_since),
+				_ => (pending, pending_since),
+			};
+			nonce = next;
+			Some(NonceRecord { next_nonce: nonce + 1, node_pending: pending, pending_since })
+		})?;
+		Ok(nonce)
+	}
+
+	// Drops the stored counter so the next nonce is taken from the node again.
+	pub fn resync(&self, chain_id: u64, address: Address) {
+		if let Err(err) = self.store.update(chain_id, address, &mut |_| None) {
+			log::error!("Failed to resync nonce of {:?} on chain {}: {}", address, chain_id, err);
+		}
+	}
+}
+
+// Process-wide manager, backed by the file at NONCE_STORE_PATH when set and in memory otherwise.
+pub fn nonce_manager() -> &'static NonceManager {
+	static MANAGER: OnceLock<NonceManager> = OnceLock::new();
+	MANAGER.get_or_init(|| match std::env::var("NONCE_STORE_PATH") {
+		Ok(path) => NonceManager::new(Box::new(FileNonceStore::new(&path))),
+		Err(_) => NonceManager::new(Box::new(MemoryNonceStore::default())),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CHAIN_ID: u64 = 1;
+
+	fn address() -> Address {
+		Address::from_low_u64_be(1)
+	}
+
+	fn store_path(name: &str) -> String {
+		std::env::temp_dir().join(format!("nonce-{}-{}.json", name, std::process::id())).to_str().unwrap().to_string()
+	}
+
+	fn file_store(name: &str) -> FileNonceStore {
+		let _ = std::fs::remove_file(store_path(name));
+		FileNonceStore::new(&store_path(name))
+	}
+
+	// A manager on each kind of store.
+	fn managers(name: &str) -> Vec<NonceManager> {
+		vec![
+			NonceManager::new(Box::new(MemoryNonceStore::default())),
+			NonceManager::new(Box::new(file_store(name))),
+		]
+	}
+
+	fn next(manager: &NonceManager, pending: u64, now: u64) -> u64 {
+		manager.next_nonce_at(CHAIN_ID, address(), pending.into(), now).unwrap().as_u64()
+	}
+
+	#[test]
+	fn allocates_sequential_nonces() {
+		for manager in managers("sequential") {
+			assert_eq!([next(&manager, 5, 0), next(&manager, 5, 1), next(&manager, 5, 2)], [5, 6, 7]);
+		}
+	}
+
+	#[test]
+	fn follows_node_pending_ahead_of_store() {
+		for manager in managers("ahead") {
+			assert_eq!(next(&manager, 3, 0), 3);
+			assert_eq!(next(&manager, 9, 1), 9);
+			assert_eq!(next(&manager, 9, 2), 10);
+		}
+	}
+
+	#[test]
+	fn resets_gap_when_node_pending_stalls() {
+		for manager in managers("gap") {
+			assert_eq!(next(&manager, 5, 0), 5);
+			// Steady traffic does not hide a nonce the node never received.
+			for now in (10..=NONCE_GAP_TIMEOUT_SECS).step_by(10) {
+				assert_eq!(next(&manager, 5, now), 5 + now / 10);
+			}
+			assert_eq!(next(&manager, 5, NONCE_GAP_TIMEOUT_SECS + 1), 5);
+			assert_eq!(next(&manager, 5, NONCE_GAP_TIMEOUT_SECS + 2), 6);
+		}
+	}
+
+	#[test]
+	fn keeps_counter_while_node_pending_advances() {
+		for manager in managers("advancing") {
+			assert_eq!(next(&manager, 5, 0), 5);
+			assert_eq!(next(&manager, 5, 1), 6);
+			assert_eq!(next(&manager, 6, 50), 7);
+			assert_eq!(next(&manager, 7, 100), 8);
+		}
+	}
+
+	#[test]
+	fn resync_takes_nonce_from_node() {
+		for manager in managers("resync") {
+			assert_eq!(next(&manager, 5, 0), 5);
+			assert_eq!(next(&manager, 5, 1), 6);
+			manager.resync(CHAIN_ID, address());
+			assert_eq!(next(&manager, 5, 2), 5);
+		}
+	}
+
+	#[test]
+	fn file_store_is_shared_between_instances() {
+		let first = NonceManager::new(Box::new(file_store("shared")));
+		let second = NonceManager::new(Box::new(FileNonceStore::new(&store_path("shared"))));
+		assert_eq!(next(&first, 5, 0), 5);
+		assert_eq!(next(&second, 5, 0), 6);
+		assert_eq!(next(&first, 5, 0), 7);
+	}
+}
@@ -0,0 +1,103 @@
+use serde_json::{json, Value};
+use ethers_core::types::U256;
+use ethers_core::abi::{decode, ParamType, Token};
+use ethers_core::utils::hex;
+use crate::abi_registry;
+use super::RpcError;
+
+// Selectors of the built-in Solidity Error(string) and Panic(uint256) reverts.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Clone, Debug)]
+pub enum RevertReason {
+	Error(String),
+	Panic(U256),
+	Custom { signature: String, args: Vec<Token> },
+	Unknown(Vec<u8>),
+}
+
+// Decodes revert data returned by a failed call, looking custom errors up in the registered ABIs.
+pub fn decode_revert_reason(data: &[u8]) -> RevertReason {
+	if data.len() < 4 {
+		return RevertReason::Unknown(data.to_vec());
+	}
+	let (selector, payload) = data.split_at(4);
+	if selector == ERROR_SELECTOR {
+		if let Ok(Some(reason)) = decode(&[ParamType::String], payload).map(|tokens| tokens[0].clone().into_string()) {
+			return RevertReason::Error(reason);
+		}
+	} else if selector == PANIC_SELECTOR {
+		if let Ok(Some(code)) = decode(&[ParamType::Uint(256)], payload).map(|tokens| tokens[0].clone().into_uint()) {
+			return RevertReason::Panic(code);
+		}
+	} else if let Some(error) = abi_registry::registry().find_error(selector) {
+		if let Ok(args) = error.decode(payload) {
+			let inputs = error.inputs.iter().map(|p| p.kind.to_string()).collect::<Vec<_>>().join(",");
+			return RevertReason::Custom { signature: format!("{}({})", error.name, inputs), args };
+		}
+	}
+	RevertReason::Unknown(data.to_vec())
+}
+
+fn panic_description(code: U256) -> &'static str {
+	match code.low_u64() {
+		0x01 => "assertion failed",
+		0x11 => "arithmetic overflow or underflow",
+		0x12 => "division or modulo by zero",
+		0x21 => "invalid enum value",
+		0x22 => "invalid storage byte array encoding",
+		0x31 => "pop on empty array",
+		0x32 => "array index out of bounds",
+		0x41 => "out of memory",
+		0x51 => "call to uninitialized function",
+		_ => "unknown panic",
+	}
+}
+
+impl RevertReason {
+	pub fn to_json(&self) -> Value {
+		match self {
+			RevertReason::Error(reason) => json!({"kind": "error", "reason": reason}),
+			RevertReason::Panic(code) => json!({"kind": "panic", "code": format!("{:#x}", code), "reason": panic_description(*code)}),
+			RevertReason::Custom { signature, args } => json!({
+				"kind": "custom",
+				"signature": signature,
+				"args": args.iter().map(abi_registry::token_to_json).collect::<Vec<Value>>(),
+				"reason": self.to_string(),
+			}),
+			RevertReason::Unknown(data) => json!({"kind": "unknown", "data": format!("0x{}", hex::encode(data))}),
+		}
+	}
+}
+
+impl std::fmt::Display for RevertReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RevertReason::Error(reason) => write!(f, "{}", reason),
+			RevertReason::Panic(code) => write!(f, "panic {:#x}: {}", code, panic_description(*code)),
+			RevertReason::Custom { signature, args } => {
+				let name = signature.split('(').next().unwrap_or_default();
+				let args = args.iter().map(|arg| abi_registry::token_to_json(arg).to_string()).collect::<Vec<_>>().join(", ");
+				write!(f, "{}({})", name, args)
+			}
+			RevertReason::Unknown(data) => write!(f, "unknown revert data 0x{}", hex::encode(data)),
+		}
+	}
+}
+
+impl RpcError {
+	// Revert data carried by a JSON-RPC error, either directly in `data` or nested as `data.data`.
+	pub fn revert_data(&self) -> Option<Vec<u8>> {
+		let data = match self {
+			RpcError::JsonRpc { data: Some(data), .. } => data,
+			_ => return None,
+		};
+		let hex_data = data.as_str().or_else(|| data["data"].as_str())?;
+		hex::decode(hex_data.trim_start_matches("0x")).ok().filter(|bytes| !bytes.is_empty())
+	}
+
+	pub fn revert_reason(&self) -> Option<RevertReason> {
+		self.revert_data().map(|data| decode_revert_reason(&data))
+	}
+}
@@ -0,0 +1,197 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use ethers_core::abi::Abi;
+use crate::cache::{cache, Ttl};
+use crate::ether_lib::http_client;
+use crate::indexer_backend::{json_address, json_string, json_u64, BackendFuture, HistoryPage, HistoryQuery, IndexerBackend, NftTransfer, TokenBalance, TokenTransfer, Transaction};
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub const CMT_TRACKING_API_URL: &str = "https://www.cmttracking.io/api";
+
+// Client for explorers speaking the Etherscan `?module=...&action=...` API: Etherscan and its
+// per-chain deployments, Blockscout, CMT tracking and similar.
+pub struct EtherscanApi {
+	base_url: String,
+	api_key: Option<String>,
+}
+
+impl EtherscanApi {
+	pub fn new(base_url: &str, api_key: Option<&str>) -> Self {
+		EtherscanApi { base_url: base_url.to_string(), api_key: api_key.filter(|key| !key.is_empty()).map(str::to_string) }
+	}
+
+	pub async fn get_transaction(&self, address: &str) -> Result<Value> {
+		self.get_request(format!("module=account&action=txlist&address={}", address).as_str()).await
+	}
+
+	pub async fn get_internal_transaction(&self, address: &str) -> Result<Value> {
+		self.get_request(format!("module=account&action=txlistinternal&address={}", address).as_str()).await
+	}
+
+	pub async fn get_erc20_transfer(&self, address: &str) -> Result<Value> {
+		self.get_request(format!("module=account&action=tokentx&address={}", address).as_str()).await
+	}
+
+	pub async fn get_nft_transfer(&self, address: &str) -> Result<Value> {
+		self.get_request(format!("module=account&action=tokennfttx&address={}", address).as_str()).await
+	}
+
+	// Every token the address holds. Not part of the Etherscan API, but served by Blockscout and CMT tracking.
+	pub async fn get_erc20_balance(&self, address: &str) -> Result<Value> {
+		self.get_request(format!("module=account&action=tokenlist&address={}", address).as_str()).await
+	}
+
+	pub async fn get_token_balance(&self, address: &str, contract_address: &str) -> Result<String> {
+		let result = self.get_request(format!("module=account&action=tokenbalance&contractaddress={}&address={}&tag=latest", contract_address, address).as_str()).await?;
+		json_string(&result).ok_or_else(|| format!("Explorer returned token balance {}", result).into())
+	}
+
+	// The verified ABI of a contract.
+	pub async fn get_abi(&self, address: &str) -> Result<Abi> {
+		let result = self.get_request(format!("module=contract&action=getabi&address={}", address).as_str()).await?;
+		let abi_json = result.as_str().ok_or_else(|| format!("Explorer returned ABI {}", result))?;
+		Ok(serde_json::from_str(abi_json)?)
+	}
+
+	// Cached for 30 seconds unless CACHE_TTLS sets "etherscan".
+	async fn get_request(&self, query: &str) -> Result<Value> {
+		let separator = if self.base_url.contains('?') { '&' } else { '?' };
+		let url = format!("{}{}{}", self.base_url, separator, query);
+		let ttl = cache().ttl("etherscan", Ttl::Seconds(30));
+		cache().get_or_fetch("etherscan", &url, ttl, self.fetch(&url), |_| true).await
+	}
+
+	async fn fetch(&self, url: &str) -> Result<Value> {
+		let mut url = url.to_string();
+		if let Some(api_key) = &self.api_key {
+			url = format!("{}&apikey={}", url, api_key);
+		}
+		let res = http_client()
+			.get(url)
+			.header("Content-Type","application/json")
+			.send()
+			.await
+			// The URL carries the API key.
+			.map_err(reqwest::Error::without_url)?;
+
+		let body = res.text().await.map_err(reqwest::Error::without_url)?;
+		let map: HashMap<String, serde_json::Value> = serde_json::from_str(body.as_str())?;
+		let result = map.get("result").cloned().ok_or_else(|| format!("Explorer response has no result: {}", body))?;
+
+		// Failures come back as status "0" with the reason in `result`, except that an empty history is
+		// also reported that way.
+		if map.get("status").and_then(Value::as_str) == Some("0") {
+			let message = map.get("message").and_then(Value::as_str).unwrap_or_default();
+			if message.starts_with("No ") && result.as_array().is_some_and(|items| items.is_empty()) {
+				return Ok(result);
+			}
+			return Err(format!("Explorer error: {} {}", message, result).into());
+		}
+		Ok(result)
+	}
+
+	// Balances of the tokens the address has transferred, for explorers without `tokenlist`.
+	async fn token_balances_from_transfers(&self, address: &str) -> Result<Vec<TokenBalance>> {
+		let mut tokens: Vec<TokenTransfer> = self.token_transfers(address, &HistoryQuery::default()).await?.items;
+		tokens.sort_by(|a, b| a.token_address.cmp(&b.token_address));
+		tokens.dedup_by(|a, b| a.token_address == b.token_address);
+		let mut balances = vec!();
+		for token in tokens {
+			balances.push(TokenBalance {
+				balance: self.get_token_balance(address, &token.token_address).await?,
+				token_address: token.token_address,
+				name: token.token_name,
+				symbol: token.token_symbol,
+				decimals: token.token_decimals,
+			});
+		}
+		Ok(balances)
+	}
+}
+
+fn result_items(result: &Value) -> Result<&Vec<Value>> {
+	result.as_array().ok_or_else(|| format!("Explorer returned {}", result).into())
+}
+
+fn parse_transaction(tx: &Value) -> Transaction {
+	Transaction {
+		hash: json_string(&tx["hash"]).unwrap_or_default(),
+		block_number: json_u64(&tx["blockNumber"]),
+		timestamp: json_u64(&tx["timeStamp"]),
+		from: json_address(&tx["from"]).unwrap_or_default(),
+		to: json_address(&tx["to"]),
+		value: json_string(&tx["value"]).unwrap_or("0".to_string()),
+		success: json_string(&tx["isError"]).map(|is_error| is_error == "0"),
+	}
+}
+
+impl IndexerBackend for EtherscanApi {
+	fn transactions<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<Transaction>> {
+		Box::pin(async move {
+			let result = self.get_transaction(address).await?;
+			HistoryPage::paginate(result_items(&result)?.iter().map(parse_transaction).collect(), query, |tx| tx.timestamp)
+		})
+	}
+
+	fn internal_transactions<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<Transaction>> {
+		Box::pin(async move {
+			let result = self.get_internal_transaction(address).await?;
+			Ok(result_items(&result)?.iter().map(parse_transaction).collect())
+		})
+	}
+
+	fn token_transfers<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<TokenTransfer>> {
+		Box::pin(async move {
+			let result = self.get_erc20_transfer(address).await?;
+			let transfers = result_items(&result)?.iter().map(|tx| TokenTransfer {
+				hash: json_string(&tx["hash"]).unwrap_or_default(),
+				block_number: json_u64(&tx["blockNumber"]),
+				timestamp: json_u64(&tx["timeStamp"]),
+				token_address: json_address(&tx["contractAddress"]).unwrap_or_default(),
+				token_name: json_string(&tx["tokenName"]),
+				token_symbol: json_string(&tx["tokenSymbol"]),
+				token_decimals: json_u64(&tx["tokenDecimal"]).map(|d| d as u32),
+				from: json_address(&tx["from"]).unwrap_or_default(),
+				to: json_address(&tx["to"]).unwrap_or_default(),
+				value: json_string(&tx["value"]).unwrap_or("0".to_string()),
+			}).collect();
+			HistoryPage::paginate(transfers, query, |transfer| transfer.timestamp)
+		})
+	}
+
+	fn nft_transfers<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<NftTransfer>> {
+		Box::pin(async move {
+			let result = self.get_nft_transfer(address).await?;
+			Ok(result_items(&result)?.iter().map(|tx| NftTransfer {
+				hash: json_string(&tx["hash"]).unwrap_or_default(),
+				block_number: json_u64(&tx["blockNumber"]),
+				timestamp: json_u64(&tx["timeStamp"]),
+				token_address: json_address(&tx["contractAddress"]).unwrap_or_default(),
+				token_name: json_string(&tx["tokenName"]),
+				token_symbol: json_string(&tx["tokenSymbol"]),
+				token_id: json_string(&tx["tokenID"]).unwrap_or_default(),
+				from: json_address(&tx["from"]).unwrap_or_default(),
+				to: json_address(&tx["to"]).unwrap_or_default(),
+			}).collect())
+		})
+	}
+
+	fn token_balances<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<TokenBalance>> {
+		Box::pin(async move {
+			let result = match self.get_erc20_balance(address).await {
+				Ok(result) => result,
+				Err(err) => {
+					log::info!("tokenlist unavailable ({}), reading balances of transferred tokens", err);
+					return self.token_balances_from_transfers(address).await;
+				}
+			};
+			Ok(result_items(&result)?.iter().filter(|token| token["type"].as_str() == Some("ERC-20")).map(|token| TokenBalance {
+				token_address: json_address(&token["contractAddress"]).unwrap_or_default(),
+				name: json_string(&token["name"]),
+				symbol: json_string(&token["symbol"]),
+				decimals: json_u64(&token["decimals"]).map(|d| d as u32),
+				balance: json_string(&token["balance"]).unwrap_or("0".to_string()),
+			}).collect())
+		})
+	}
+}
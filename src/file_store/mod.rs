@@ -0,0 +1,120 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// A lock file older than this was left behind by an invocation that died holding it.
+const STALE_LOCK: Duration = Duration::from_secs(10);
+const LOCK_RETRY: Duration = Duration::from_millis(10);
+
+// One JSON document in a file shared by function invocations running side by side. Each update holds the
+// lock file next to it, which invocations create in turn, and replaces the file by renaming a complete
+// copy over it, so readers never see a partial write.
+pub struct JsonFileStore {
+	path: String,
+	lock: Mutex<()>,
+}
+
+// Removes the lock file when the update is done, whether or not it succeeded.
+struct FileLock(String);
+
+impl Drop for FileLock {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.0);
+	}
+}
+
+impl JsonFileStore {
+	pub fn new(path: &str) -> Self {
+		JsonFileStore { path: path.to_string(), lock: Mutex::new(()) }
+	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	// The document, or its default when the file does not exist yet. A file that does not parse is an
+	// error rather than an empty document, which the next update would write back over it.
+	pub fn read<T: DeserializeOwned + Default>(&self) -> std::io::Result<T> {
+		match std::fs::read_to_string(&self.path) {
+			Ok(content) => serde_json::from_str(&content).map_err(|err| Error::new(ErrorKind::InvalidData, format!("corrupt store {}: {}", self.path, err))),
+			Err(err) if err.kind() == ErrorKind::NotFound => Ok(T::default()),
+			Err(err) => Err(err),
+		}
+	}
+
+	// Applies `update` to the document with no other update in between, in this process or another.
+	pub fn update<T: Serialize + DeserializeOwned + Default, R>(&self, update: impl FnOnce(&mut T) -> R) -> std::io::Result<R> {
+		let _guard = self.lock.lock().unwrap();
+		let _lock = self.lock_file()?;
+		let mut document = self.read()?;
+		let result = update(&mut document);
+		let tmp_path = format!("{}.tmp", self.path);
+		std::fs::write(&tmp_path, serde_json::to_vec(&document)?)?;
+		std::fs::rename(&tmp_path, &self.path)?;
+		Ok(result)
+	}
+
+	fn lock_file(&self) -> std::io::Result<FileLock> {
+		let lock_path = format!("{}.lock", self.path);
+		loop {
+			match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+				Ok(_) => return Ok(FileLock(lock_path)),
+				Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+					let age = std::fs::metadata(&lock_path).and_then(|meta| meta.modified()).ok().and_then(|modified| modified.elapsed().ok());
+					if age.is_some_and(|age| age > STALE_LOCK) {
+						log::warn!("Removing stale store lock {}", lock_path);
+						let _ = std::fs::remove_file(&lock_path);
+					} else {
+						std::thread::sleep(LOCK_RETRY);
+					}
+				}
+				Err(err) => return Err(err),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	fn store(name: &str) -> JsonFileStore {
+		let path = std::env::temp_dir().join(format!("store-{}-{}.json", name, std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		JsonFileStore::new(path.to_str().unwrap())
+	}
+
+	#[test]
+	fn updates_are_not_lost_between_instances() {
+		let first = store("instances");
+		let threads: Vec<_> = (0..4)
+			.map(|_| {
+				let path = first.path().to_string();
+				std::thread::spawn(move || {
+					let store = JsonFileStore::new(&path);
+					for _ in 0..25 {
+						store.update(|counts: &mut HashMap<String, u64>| *counts.entry("count".to_string()).or_default() += 1).unwrap();
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+		assert_eq!(first.read::<HashMap<String, u64>>().unwrap()["count"], 100);
+	}
+
+	#[test]
+	fn corrupt_file_is_an_error() {
+		let store = store("corrupt");
+		assert!(store.read::<HashMap<String, u64>>().unwrap().is_empty());
+		std::fs::write(store.path(), "{\"count\": ").unwrap();
+		assert_eq!(store.read::<HashMap<String, u64>>().unwrap_err().kind(), ErrorKind::InvalidData);
+		assert!(store.update(|counts: &mut HashMap<String, u64>| counts.clear()).is_err());
+		assert_eq!(std::fs::read_to_string(store.path()).unwrap(), "{\"count\": ");
+	}
+}
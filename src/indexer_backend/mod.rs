@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use crate::etherscan_api::{EtherscanApi, CMT_TRACKING_API_URL};
+use crate::moralis_api::MoralisApi;
+
+mod rpc;
+pub use rpc::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
+
+// Addresses are lowercase 0x hex, amounts are decimal strings in the smallest unit and timestamps are
+// unix seconds, whichever explorer they come from.
+#[derive(Clone, Debug, Serialize)]
+pub struct Transaction {
+	pub hash: String,
+	pub block_number: Option<u64>,
+	pub timestamp: Option<u64>,
+	pub from: String,
+	pub to: Option<String>,
+	pub value: String,
+	pub success: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenTransfer {
+	pub hash: String,
+	pub block_number: Option<u64>,
+	pub timestamp: Option<u64>,
+	pub token_address: String,
+	pub token_name: Option<String>,
+	pub token_symbol: Option<String>,
+	pub token_decimals: Option<u32>,
+	pub from: String,
+	pub to: String,
+	pub value: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NftTransfer {
+	pub hash: String,
+	pub block_number: Option<u64>,
+	pub timestamp: Option<u64>,
+	pub token_address: String,
+	pub token_name: Option<String>,
+	pub token_symbol: Option<String>,
+	pub token_id: String,
+	pub from: String,
+	pub to: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenBalance {
+	pub token_address: String,
+	pub name: Option<String>,
+	pub symbol: Option<String>,
+	pub decimals: Option<u32>,
+	pub balance: String,
+}
+
+// Which part of a history to return. Dates are unix seconds, inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryQuery {
+	pub cursor: Option<String>,
+	pub limit: Option<usize>,
+	pub from_date: Option<u64>,
+	pub to_date: Option<u64>,
+}
+
+pub struct HistoryPage<T> {
+	pub items: Vec<T>,
+	pub next_cursor: Option<String>,
+}
+
+impl<T> HistoryPage<T> {
+	// Pages a complete history for backends without server-side paging: filters it by date, then uses
+	// the cursor as an offset into what is left.
+	pub fn paginate(items: Vec<T>, query: &HistoryQuery, timestamp: impl Fn(&T) -> Option<u64>) -> Result<Self> {
+		let offset = match &query.cursor {
+			Some(cursor) => cursor.parse::<usize>().map_err(|_| format!("Invalid cursor: {}", cursor))?,
+			None => 0,
+		};
+		let mut items: Vec<T> = items
+			.into_iter()
+			.filter(|item| {
+				let time = timestamp(item);
+				query.from_date.is_none_or(|from| time.is_some_and(|time| time >= from))
+					&& query.to_date.is_none_or(|to| time.is_some_and(|time| time <= to))
+			})
+			.skip(offset)
+			.collect();
+		let next_cursor = match query.limit {
+			Some(limit) if items.len() > limit => {
+				items.truncate(limit);
+				Some((offset + limit).to_string())
+			}
+			_ => None,
+		};
+		Ok(HistoryPage { items, next_cursor })
+	}
+}
+
+// Account history from a block explorer or indexing service.
+pub trait IndexerBackend {
+	fn transactions<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<Transaction>>;
+	fn token_transfers<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<TokenTransfer>>;
+	fn token_balances<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<TokenBalance>>;
+
+	// Token transfers with the current balances of the address, for backends that can read both at once.
+	fn token_transfers_and_balances<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, (HistoryPage<TokenTransfer>, Vec<TokenBalance>)> {
+		Box::pin(async move { Ok((self.token_transfers(address, query).await?, self.token_balances(address).await?)) })
+	}
+
+	fn internal_transactions<'a>(&'a self, _address: &'a str) -> BackendFuture<'a, Vec<Transaction>> {
+		Box::pin(async { Err("Internal transactions are not supported by this backend".into()) })
+	}
+
+	fn nft_transfers<'a>(&'a self, _address: &'a str) -> BackendFuture<'a, Vec<NftTransfer>> {
+		Box::pin(async { Err("NFT transfers are not supported by this backend".into()) })
+	}
+}
+
+// Which history backend serves a chain, as written in the chain configuration, such as
+// {"backend": "etherscan", "api_url": "https://api.etherscan.io/api", "api_key": "..."}.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum ExplorerConfig {
+	Etherscan {
+		api_url: String,
+		#[serde(default)]
+		api_key: Option<String>,
+	},
+	Cmt {
+		#[serde(default)]
+		api_url: Option<String>,
+	},
+	Moralis {
+		api_key: String,
+	},
+	Rpc {
+		#[serde(default)]
+		from_block: Option<u64>,
+		#[serde(default)]
+		max_lookback: Option<u64>,
+		#[serde(default = "default_scan_blocks")]
+		scan_blocks: u64,
+	},
+}
+
+fn default_scan_blocks() -> u64 {
+	1000
+}
+
+impl ExplorerConfig {
+	// Backend named by INDEXER_BACKEND ("etherscan", "cmt", "moralis" or "rpc"), configured by its own
+	// variables. When unset, CyberMiles (chain 18) uses CMT tracking and every other chain Moralis.
+	pub fn from_env(chain_id: u64) -> Result<Self> {
+		let var = |name: &str| std::env::var(name).ok();
+		let number = |name: &str, default: u64| var(name).and_then(|v| v.parse().ok()).unwrap_or(default);
+		let name = var("INDEXER_BACKEND").unwrap_or(if chain_id == 18 { "cmt" } else { "moralis" }.to_string());
+		match name.as_str() {
+			"etherscan" => Ok(ExplorerConfig::Etherscan {
+				api_url: var("ETHERSCAN_API_URL").unwrap_or("https://api.etherscan.io/api".to_string()),
+				api_key: var("ETHERSCAN_API_KEY"),
+			}),
+			"cmt" => Ok(ExplorerConfig::Cmt { api_url: var("CMT_API_URL") }),
+			"moralis" => Ok(ExplorerConfig::Moralis { api_key: var("MORALIS_API_KEY").unwrap_or("".to_string()) }),
+			"rpc" => Ok(ExplorerConfig::Rpc {
+				from_block: var("RPC_HISTORY_FROM_BLOCK").and_then(|v| v.parse().ok()),
+				max_lookback: var("RPC_HISTORY_MAX_LOOKBACK").and_then(|v| v.parse().ok()),
+				scan_blocks: number("RPC_HISTORY_SCAN_BLOCKS", default_scan_blocks()),
+			}),
+			_ => Err(format!("Unknown INDEXER_BACKEND: {}", name).into()),
+		}
+	}
+
+	pub fn backend(&self, chain_id: u64, rpc_node_url: &str) -> Box<dyn IndexerBackend> {
+		match self {
+			ExplorerConfig::Etherscan { api_url, api_key } => Box::new(EtherscanApi::new(api_url, api_key.as_deref())),
+			ExplorerConfig::Cmt { api_url } => Box::new(EtherscanApi::new(api_url.as_deref().unwrap_or(CMT_TRACKING_API_URL), None)),
+			ExplorerConfig::Moralis { api_key } => Box::new(MoralisApi::new(api_key, chain_id)),
+			ExplorerConfig::Rpc { from_block, max_lookback, scan_blocks } => Box::new(RpcBackend::new(rpc_node_url, *from_block, *max_lookback, *scan_blocks)),
+		}
+	}
+}
+
+// Explorers encode numbers as JSON numbers or as decimal strings.
+pub fn json_u64(value: &Value) -> Option<u64> {
+	match value {
+		Value::Number(n) => n.as_u64(),
+		Value::String(s) => s.parse().ok(),
+		_ => None,
+	}
+}
+
+pub fn json_string(value: &Value) -> Option<String> {
+	value.as_str().filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+pub fn json_address(value: &Value) -> Option<String> {
+	json_string(value).map(|address| address.to_lowercase())
+}
+
+// Unix seconds of a date given as unix seconds, as "2021-05-07" or as "2021-05-07T11:08:35.000Z" (UTC, with
+// optional fractional seconds and `Z`). Dates that do not exist and other UTC offsets are rejected.
+pub fn parse_date(s: &str) -> Option<u64> {
+	if let Ok(seconds) = s.parse::<u64>() {
+		return Some(seconds);
+	}
+	let field = |s: &str, digits: usize| match s.len() == digits && s.bytes().all(|b| b.is_ascii_digit()) {
+		true => s.parse::<i64>().ok(),
+		false => None,
+	};
+	let (date, time) = match s.split_once('T') {
+		Some((date, time)) => (date, Some(time)),
+		None => (s, None),
+	};
+	let [year, month, day] = fields(date, '-')?;
+	let (year, month, day) = (field(year, 4)?, field(month, 2)?, field(day, 2)?);
+	if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+		return None;
+	}
+	let (hour, minute, second) = match time {
+		Some(time) => {
+			let time = time.strip_suffix('Z').unwrap_or(time);
+			let time = match time.split_once('.') {
+				Some((time, fraction)) if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) => time,
+				Some(_) => return None,
+				None => time,
+			};
+			let [hour, minute, second] = fields(time, ':')?;
+			(field(hour, 2).filter(|h| *h < 24)?, field(minute, 2).filter(|m| *m < 60)?, field(second, 2).filter(|s| *s < 60)?)
+		}
+		None => (0, 0, 0),
+	};
+	// Days since 1970-01-01 of the proleptic Gregorian date, counting years from March.
+	let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+	let era = y.div_euclid(400);
+	let year_of_era = y - era * 400;
+	let day_of_year = (153 * m + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	let days = era * 146097 + day_of_era - 719468;
+	u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+// Exactly three fields of `s` separated by `separator`.
+fn fields(s: &str, separator: char) -> Option<[&str; 3]> {
+	s.split(separator).collect::<Vec<_>>().try_into().ok()
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+	match month {
+		2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_known_dates() {
+		assert_eq!(parse_date("1620345600"), Some(1620345600));
+		assert_eq!(parse_date("1970-01-01"), Some(0));
+		assert_eq!(parse_date("2021-05-07"), Some(1620345600));
+		assert_eq!(parse_date("2021-05-07T11:08:35.000Z"), Some(1620385715));
+		assert_eq!(parse_date("2021-05-07T11:08:35Z"), Some(1620385715));
+		assert_eq!(parse_date("2021-05-07T11:08:35"), Some(1620385715));
+		assert_eq!(parse_date("2000-02-29T00:00:00Z"), Some(951782400));
+		assert_eq!(parse_date("2024-12-31T23:59:59Z"), Some(1735689599));
+	}
+
+	#[test]
+	fn rejects_invalid_dates() {
+		for date in [
+			"", "-1", "2021-05", "2021-5-7", "2021/05/07", "2021-05-07-01", "2021-00-10", "2021-13-01", "2021-04-31", "2021-02-29", "1900-02-29",
+			"1969-12-31", "2021-05-07Z", "2021-05-07T", "2021-05-07T11:08", "2021-05-07 11:08:35", "2021-05-07T24:00:00Z", "2021-05-07T11:60:00Z",
+			"2021-05-07T11:08:60Z", "2021-05-07T11:08:35.Z", "2021-05-07T11:08:35+02:00", "2021-05-07T11:08:35.000+00:00", "2021-05-07T11:08:35ZZ",
+		] {
+			assert_eq!(parse_date(date), None, "{}", date);
+		}
+	}
+}
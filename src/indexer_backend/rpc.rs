@@ -0,0 +1,221 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use ethers_core::abi::{decode, ParamType};
+use ethers_core::types::{Address, Log, H256, U256};
+use ethers_core::utils::{hex, keccak256};
+use crate::ether_lib::{get_block_number, json_rpc_batch, scan_logs, RpcResult};
+use super::{BackendFuture, HistoryPage, HistoryQuery, IndexerBackend, TokenBalance, TokenTransfer, Transaction};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const NAME_SELECTOR: &str = "0x06fdde03";
+const SYMBOL_SELECTOR: &str = "0x95d89b41";
+const DECIMALS_SELECTOR: &str = "0x313ce567";
+const BALANCE_OF_SELECTOR: &str = "0x70a08231";
+
+// Blocks fetched per batch request while scanning for native transfers.
+const BLOCK_BATCH_SIZE: u64 = 100;
+
+#[derive(Default)]
+struct TokenMetadata {
+	name: Option<String>,
+	symbol: Option<String>,
+	decimals: Option<u32>,
+}
+
+// History read from the node alone: ERC-20 transfers from Transfer logs from `from_block`, and no further
+// back than `max_lookback` blocks, native transfers by scanning the transactions of recent blocks.
+pub struct RpcBackend {
+	rpc_node_url: String,
+	from_block: Option<u64>,
+	max_lookback: Option<u64>,
+	scan_blocks: u64,
+}
+
+impl RpcBackend {
+	pub fn new(rpc_node_url: &str, from_block: Option<u64>, max_lookback: Option<u64>, scan_blocks: u64) -> Self {
+		RpcBackend { rpc_node_url: rpc_node_url.to_string(), from_block, max_lookback, scan_blocks: scan_blocks.max(1) }
+	}
+
+	// First block of the Transfer log scan. Without a bound, the scan would read every log of the chain.
+	fn first_log_block(&self, latest: u64) -> Result<u64> {
+		let lookback = self.max_lookback.map(|lookback| latest.saturating_sub(lookback.saturating_sub(1)));
+		match (self.from_block, lookback) {
+			(None, None) => Err("The rpc history backend needs from_block or max_lookback to read token transfers".into()),
+			(from_block, lookback) => Ok(from_block.unwrap_or_default().max(lookback.unwrap_or_default())),
+		}
+	}
+
+	// ERC-20 Transfer logs with `address` as sender (topic1) or recipient (topic2).
+	async fn transfer_logs(&self, address: Address) -> Result<Vec<Log>> {
+		let transfer_topic = H256::from(keccak256("Transfer(address,address,uint256)"));
+		let account = H256::from(address);
+		let latest = get_block_number(&self.rpc_node_url).await?;
+		let first = self.first_log_block(latest)?;
+		let mut logs = vec!();
+		for topics in [json!([transfer_topic, account]), json!([transfer_topic, null, account])] {
+			logs.extend(scan_logs(&self.rpc_node_url, None, topics, first, Some(latest)).await?);
+		}
+		// ERC-721 shares the Transfer signature but indexes the token id as a fourth topic.
+		logs.retain(|log| log.topics.len() == 3 && log.data.len() == 32);
+		logs.sort_by_key(|log| (log.block_number, log.log_index));
+		// Transfers to oneself match both scans.
+		logs.dedup_by_key(|log| (log.transaction_hash, log.log_index));
+		Ok(logs)
+	}
+
+	async fn block_timestamps(&self, blocks: &BTreeSet<u64>) -> Result<BTreeMap<u64, u64>> {
+		let calls = blocks.iter().map(|number| ("eth_getBlockByNumber", json!([format!("{:#x}", number), false]))).collect();
+		let results = json_rpc_batch(&self.rpc_node_url, calls).await?;
+		Ok(blocks.iter().zip(results).filter_map(|(number, block)| Some((*number, hex_u64(&block.ok()?["timestamp"])?))).collect())
+	}
+
+	async fn token_metadata(&self, tokens: &[Address]) -> Result<HashMap<Address, TokenMetadata>> {
+		let calls = tokens
+			.iter()
+			.flat_map(|token| [NAME_SELECTOR, SYMBOL_SELECTOR, DECIMALS_SELECTOR].map(|selector| ("eth_call", json!([{"to": token, "data": selector}, "latest"]))))
+			.collect();
+		let results = json_rpc_batch(&self.rpc_node_url, calls).await?;
+		Ok(tokens.iter().zip(results.chunks(3)).map(|(token, results)| (*token, TokenMetadata {
+			name: decode_string(&results[0]),
+			symbol: decode_string(&results[1]),
+			decimals: decode_uint(&results[2]).map(|d| d.low_u32()),
+		})).collect())
+	}
+
+	// Transfers of `logs` with their block timestamps and token metadata.
+	async fn transfers_from_logs(&self, logs: &[Log], metadata: &HashMap<Address, TokenMetadata>) -> Result<Vec<TokenTransfer>> {
+		let blocks: BTreeSet<u64> = logs.iter().filter_map(|log| log.block_number).map(|n| n.as_u64()).collect();
+		let timestamps = self.block_timestamps(&blocks).await?;
+		Ok(logs.iter().map(|log| {
+			let token = metadata.get(&log.address);
+			let block_number = log.block_number.map(|n| n.as_u64());
+			TokenTransfer {
+				hash: format!("{:?}", log.transaction_hash.unwrap_or_default()),
+				block_number,
+				timestamp: block_number.and_then(|n| timestamps.get(&n).copied()),
+				token_address: format!("{:?}", log.address),
+				token_name: token.and_then(|t| t.name.clone()),
+				token_symbol: token.and_then(|t| t.symbol.clone()),
+				token_decimals: token.and_then(|t| t.decimals),
+				from: format!("{:?}", Address::from(log.topics[1])),
+				to: format!("{:?}", Address::from(log.topics[2])),
+				value: U256::from_big_endian(&log.data).to_string(),
+			}
+		}).collect())
+	}
+
+	// Balances of `account` in `tokens`, leaving out emptied ones.
+	async fn balances_of(&self, account: Address, tokens: &[Address], mut metadata: HashMap<Address, TokenMetadata>) -> Result<Vec<TokenBalance>> {
+		let data = format!("{}{:0>64}", BALANCE_OF_SELECTOR, hex::encode(account));
+		let calls = tokens.iter().map(|token| ("eth_call", json!([{"to": token, "data": data}, "latest"]))).collect();
+		let balances = json_rpc_batch(&self.rpc_node_url, calls).await?;
+		Ok(tokens.iter().zip(balances).filter_map(|(token, balance)| {
+			let balance = decode_uint(&balance).filter(|balance| !balance.is_zero())?;
+			let token_metadata = metadata.remove(token).unwrap_or_default();
+			Some(TokenBalance {
+				token_address: format!("{:?}", token),
+				name: token_metadata.name,
+				symbol: token_metadata.symbol,
+				decimals: token_metadata.decimals,
+				balance: balance.to_string(),
+			})
+		}).collect())
+	}
+}
+
+// Tokens of `logs`, each once.
+fn log_tokens(logs: &[Log]) -> Vec<Address> {
+	logs.iter().map(|log| log.address).collect::<BTreeSet<_>>().into_iter().collect()
+}
+
+fn hex_u64(value: &Value) -> Option<u64> {
+	serde_json::from_value::<U256>(value.clone()).ok().map(|n| n.low_u64())
+}
+
+fn call_output(result: &RpcResult<Value>) -> Option<Vec<u8>> {
+	hex::decode(result.as_ref().ok()?.as_str()?.trim_start_matches("0x")).ok()
+}
+
+// Token names are strings, but some early tokens return them as bytes32.
+fn decode_string(result: &RpcResult<Value>) -> Option<String> {
+	let output = call_output(result)?;
+	match decode(&[ParamType::String], &output) {
+		Ok(tokens) => tokens[0].clone().into_string(),
+		Err(_) if output.len() == 32 => Some(String::from_utf8_lossy(&output).trim_end_matches('\0').to_string()),
+		Err(_) => None,
+	}.filter(|s| !s.is_empty())
+}
+
+fn decode_uint(result: &RpcResult<Value>) -> Option<U256> {
+	decode(&[ParamType::Uint(256)], &call_output(result)?).ok()?[0].clone().into_uint()
+}
+
+impl IndexerBackend for RpcBackend {
+	fn transactions<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<Transaction>> {
+		Box::pin(async move {
+			let account = address.to_lowercase();
+			let latest = get_block_number(&self.rpc_node_url).await?;
+			let first = latest.saturating_sub(self.scan_blocks - 1).max(self.from_block.unwrap_or_default());
+			let mut transactions = vec!();
+			let mut start = first;
+			while start <= latest {
+				let end = latest.min(start + BLOCK_BATCH_SIZE - 1);
+				let calls = (start..=end).map(|number| ("eth_getBlockByNumber", json!([format!("{:#x}", number), true]))).collect();
+				for block in json_rpc_batch(&self.rpc_node_url, calls).await? {
+					let block = block?;
+					let timestamp = hex_u64(&block["timestamp"]);
+					for tx in block["transactions"].as_array().into_iter().flatten() {
+						let from = tx["from"].as_str().unwrap_or_default().to_lowercase();
+						let to = tx["to"].as_str().map(str::to_lowercase);
+						if from != account && to.as_deref() != Some(account.as_str()) {
+							continue;
+						}
+						transactions.push(Transaction {
+							hash: tx["hash"].as_str().unwrap_or_default().to_string(),
+							block_number: hex_u64(&tx["blockNumber"]),
+							timestamp,
+							from,
+							to,
+							value: serde_json::from_value::<U256>(tx["value"].clone()).unwrap_or_default().to_string(),
+							success: None,
+						});
+					}
+				}
+				start = end + 1;
+			}
+			HistoryPage::paginate(transactions, query, |tx| tx.timestamp)
+		})
+	}
+
+	fn token_transfers<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<TokenTransfer>> {
+		Box::pin(async move {
+			let logs = self.transfer_logs(Address::from_str(address)?).await?;
+			let metadata = self.token_metadata(&log_tokens(&logs)).await?;
+			HistoryPage::paginate(self.transfers_from_logs(&logs, &metadata).await?, query, |transfer| transfer.timestamp)
+		})
+	}
+
+	// Balances of the tokens the address has sent or received, leaving out emptied ones.
+	fn token_balances<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<TokenBalance>> {
+		Box::pin(async move {
+			let account = Address::from_str(address)?;
+			let tokens = log_tokens(&self.transfer_logs(account).await?);
+			let metadata = self.token_metadata(&tokens).await?;
+			self.balances_of(account, &tokens, metadata).await
+		})
+	}
+
+	// Both from one scan of the Transfer logs.
+	fn token_transfers_and_balances<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, (HistoryPage<TokenTransfer>, Vec<TokenBalance>)> {
+		Box::pin(async move {
+			let account = Address::from_str(address)?;
+			let logs = self.transfer_logs(account).await?;
+			let tokens = log_tokens(&logs);
+			let metadata = self.token_metadata(&tokens).await?;
+			let transfers = HistoryPage::paginate(self.transfers_from_logs(&logs, &metadata).await?, query, |transfer| transfer.timestamp)?;
+			Ok((transfers, self.balances_of(account, &tokens, metadata).await?))
+		})
+	}
+}
@@ -1,19 +1,26 @@
-use webhook_flows::{create_endpoint, request_handler, send_response, route::{get, route, RouteError, Router}};
+use webhook_flows::{create_endpoint, request_handler, send_response, route::{get, post, route, RouteError, Router}};
 use flowsnet_platform_sdk::logger;
-use ethers_core::rand;
 use ethers_core::utils::hex;
-use ethers_core::types::{NameOrAddress, Bytes, U256, H160};
-use ethers_signers::{LocalWallet, Signer, MnemonicBuilder, coins_bip39::English};
+use ethers_core::types::{NameOrAddress, Bytes, Signature, Transaction, U256, H160, H256};
+use ethers_signers::Signer;
+use zeroize::Zeroizing;
 use serde_json::Value;
 use serde_json::json;
 use std::collections::HashMap;
 use std::str::FromStr;
-use ethers_core::abi::Token;
+use std::time::Duration;
+use ethers_core::abi::{Function, Token};
 // use core::time::Duration;
 
 pub mod ether_lib;
-pub mod cmt_api;
+pub mod etherscan_api;
 pub mod moralis_api;
+pub mod abi_registry;
+pub mod indexer_backend;
+pub mod cache;
+pub mod file_store;
+pub mod chains;
+pub mod wallet;
 use ether_lib::*;
 
 #[no_mangle]
@@ -35,7 +42,28 @@ async fn handler(_headers: Vec<(String, String)>, _subpath: String, _qry: HashMa
     router
         .insert(
             "/gen-key",
-            vec![get(gen_key)],
+            vec![get(gen_key), post(gen_key)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/hd/accounts",
+            vec![get(hd_accounts), post(hd_accounts)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/sign-message",
+            vec![get(sign_message), post(sign_message)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/verify-message",
+            vec![get(verify_message), post(verify_message)],
         )
         .unwrap();
 
@@ -46,6 +74,34 @@ async fn handler(_headers: Vec<(String, String)>, _subpath: String, _qry: HashMa
         )
         .unwrap();
 
+    router
+        .insert(
+            "/speed-up",
+            vec![get(speed_up)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/cancel",
+            vec![get(cancel)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/contract/call",
+            vec![get(contract_call), post(contract_call)],
+        )
+        .unwrap();
+
+    router
+        .insert(
+            "/contract/send",
+            vec![get(contract_send), post(contract_send)],
+        )
+        .unwrap();
+
     router
         .insert(
             "/get_txs",
@@ -94,6 +150,18 @@ async fn handler(_headers: Vec<(String, String)>, _subpath: String, _qry: HashMa
             vec![get(get_erc20_to_txs)],
         )
         .unwrap();
+    router
+        .insert(
+            "/cache-metrics",
+            vec![get(cache_metrics)],
+        )
+        .unwrap();
+    router
+        .insert(
+            "/rpc-health",
+            vec![get(rpc_health)],
+        )
+        .unwrap();
 
     if let Err(e) = route(router).await {
         match e {
@@ -107,140 +175,803 @@ async fn handler(_headers: Vec<(String, String)>, _subpath: String, _qry: HashMa
     }
 }
 
+fn send_rpc_error(err: &RpcError) {
+    log::error!("RPC error: {}", err);
+    let (status, res_json) = match err {
+        RpcError::JsonRpc { code, message, data } => match err.revert_reason() {
+            Some(reason) => (400, json!({"error": format!("execution reverted: {}", reason), "code": code, "revert": reason.to_json(), "data": data})),
+            // Invalid request and invalid params are the caller's mistake, anything else is the node's.
+            None if *code == -32600 || *code == -32602 => (400, json!({"error": message, "code": code, "data": data})),
+            None => (502, json!({"error": message, "code": code, "data": data})),
+        },
+        _ => (502, json!({"error": err.to_string()})),
+    };
+    send_response(
+        status,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
+    );
+}
+
+fn send_bad_request(message: &str) {
+    send_error_message(400, message);
+}
+
+fn send_error_message(status: u16, message: &str) {
+    send_response(
+        status,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&json!({"error": message})).unwrap(),
+    );
+}
+
+fn send_error(err: &(dyn std::error::Error + Send + Sync + 'static)) {
+    if let Some(rpc_err) = err.downcast_ref::<RpcError>() {
+        return send_rpc_error(rpc_err);
+    }
+    log::error!("Error: {}", err);
+    send_response(
+        500,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&json!({"error": err.to_string()})).unwrap(),
+    );
+}
+
 
 async fn gen_key(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("Gen key Query -- {:?}", _qry);
-    let wallet;
-    if let Some(_phrase) = _qry.get("phrase") {
-        let phrase = _qry.get("phrase").unwrap().as_str().unwrap().trim_matches('"');
-        wallet = MnemonicBuilder::<English>::default()
-        .phrase(phrase)
-        .build()
-        .unwrap();
-    } else {
-        let mut rng = rand::thread_rng();
-        wallet = MnemonicBuilder::<English>::default()
-        .word_count(24)
-        .derivation_path("m/44'/60'/0'/2/1")
-        .unwrap()
-        .build_random(&mut rng)
-        .unwrap();   
+    let params = request_params(_qry, &_body);
+    match gen_key_response(&params) {
+        Ok((content_type, body)) => send_response(
+            200,
+            vec![(String::from("content-type"), String::from(content_type))],
+            body,
+        ),
+        Err(e) => send_bad_request(&e),
+    }
+}
+
+// With `phrase`, the account of that mnemonic at m/44'/60'/0'/0/0, otherwise a new account of a random
+// 24-word mnemonic at m/44'/60'/0'/2/1. Only the address is returned, unless `export` asks for the
+// mnemonic of a new account and, given `password`, the keystore of its key. Secrets are never logged.
+fn gen_key_response(params: &HashMap<String, Value>) -> Result<(&'static str, Vec<u8>), String> {
+    log::info!("Gen key Query -- {:?}", redact_params(params));
+    let secret = |name: &str| param(params, name).map(Zeroizing::new);
+    let export = secret("export").is_some_and(|export| matches!(export.as_str(), "true" | "1"));
+
+    let phrase = secret("phrase");
+    let generated = match phrase {
+        Some(_) => None,
+        None => Some(wallet::generate_mnemonic(wallet::Language::English, 24).map_err(|e| e.to_string())?),
+    };
+    let (phrase, path) = match (&generated, &phrase) {
+        (Some(generated), _) => (generated.as_str(), "m/44'/60'/0'/2/1"),
+        (None, phrase) => (phrase.as_deref().map(String::as_str).unwrap_or_default(), "m/44'/60'/0'/0/0"),
+    };
+    let wallet = wallet::derive_wallets(phrase, wallet::Language::English, None, &[path.to_string()]).map_err(|e| e.to_string())?.remove(0);
+    log::info!("Your address is: {:?}", wallet.address());
+    if !export {
+        return Ok(("text/html", format!("Your address is: {:?}.", wallet.address()).into_bytes()));
+    }
+
+    let mut res_json = json!({"address": format!("{:?}", wallet.address()), "path": path});
+    if let Some(generated) = &generated {
+        res_json["mnemonic"] = json!(generated.as_str());
+    }
+    if let Some(password) = secret("password") {
+        res_json["keystore"] = json!(wallet::encrypt_keystore(&wallet, password.as_bytes()).map_err(|e| e.to_string())?);
+    }
+    Ok(("application/json", serde_json::to_vec_pretty(&res_json).unwrap()))
+}
+
+// Parameters holding secrets, which request logs leave out.
+const SENSITIVE_PARAMS: [&str; 6] = ["phrase", "passphrase", "mnemonic", "password", "keystore_password", "private_key"];
+
+fn redact_params(params: &HashMap<String, Value>) -> HashMap<String, Value> {
+    params
+        .iter()
+        .map(|(name, value)| match SENSITIVE_PARAMS.contains(&name.as_str()) {
+            true => (name.clone(), json!("[redacted]")),
+            false => (name.clone(), value.clone()),
+        })
+        .collect()
+}
+
+struct HdRequest {
+    phrase: Option<String>,
+    passphrase: Option<String>,
+    language: wallet::Language,
+    word_count: usize,
+    paths: Vec<String>,
+    keystore_password: Option<String>,
+}
+
+fn parse_hd_request(params: &HashMap<String, Value>) -> Result<HdRequest, String> {
+    let number = |name: &str, default: u32| param(params, name).map(|v| v.parse::<u32>().map_err(|_| format!("Invalid {}: {}", name, v))).unwrap_or(Ok(default));
+    let paths = match params.get("paths") {
+        Some(Value::Array(paths)) => paths.iter().map(|path| path.as_str().map(str::to_string).ok_or(format!("Invalid path: {}", path))).collect::<Result<Vec<_>, _>>()?,
+        Some(_) => param(params, "paths").unwrap_or_default().split(',').map(|path| path.trim().to_string()).filter(|path| !path.is_empty()).collect(),
+        None => wallet::account_paths(&param(params, "path").unwrap_or(wallet::DEFAULT_HD_PATH.to_string()), number("start", 0)?, number("count", 1)?).map_err(|e| e.to_string())?,
+    };
+    if paths.is_empty() || paths.len() > wallet::MAX_HD_ACCOUNTS as usize {
+        return Err(format!("Between 1 and {} paths are required", wallet::MAX_HD_ACCOUNTS));
     }
+    Ok(HdRequest {
+        phrase: param(params, "phrase"),
+        passphrase: param(params, "passphrase"),
+        language: param(params, "language").map(|v| wallet::Language::from_str(&v)).transpose()?.unwrap_or_default(),
+        word_count: number("word_count", 24)? as usize,
+        paths,
+        keystore_password: param(params, "keystore_password"),
+    })
+}
+
+// Derives accounts from `phrase`, or from a new mnemonic of `word_count` words in `language` which is
+// returned with them. Accounts are at `path`/index for `count` indices from `start`, or at each of
+// `paths`, and come with their keystore encrypted with `keystore_password` when it is given.
+async fn hd_accounts(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    let params = request_params(_qry, &_body);
+    log::info!("HD accounts Query -- {:?}", redact_params(&params));
+
+    let request = match parse_hd_request(&params) {
+        Ok(request) => request,
+        Err(e) => return send_bad_request(&e),
+    };
+    let generated = match request.phrase {
+        Some(_) => None,
+        None => match wallet::generate_mnemonic(request.language, request.word_count) {
+            Ok(phrase) => Some(phrase),
+            Err(e) => return send_bad_request(&e.to_string()),
+        },
+    };
+    let phrase = generated.as_deref().or(request.phrase.as_ref()).map(String::as_str).unwrap_or_default();
+    let wallets = match wallet::derive_wallets(phrase, request.language, request.passphrase.as_deref(), &request.paths) {
+        Ok(wallets) => wallets,
+        Err(e) => return send_bad_request(&e.to_string()),
+    };
 
-    log::info!("Your address is: {:?}, private key: 0x{}", wallet.address(), hex::encode(wallet.signer().to_bytes()));
-    let resp = format!("Your address is: {:?}.", wallet.address()); 
+    let mut accounts = vec!();
+    for (path, wallet) in request.paths.iter().zip(&wallets) {
+        let mut account = json!({"path": path, "address": format!("{:?}", wallet.address())});
+        if let Some(password) = &request.keystore_password {
+            match wallet::encrypt_keystore(wallet, password.as_bytes()) {
+                Ok(keystore) => account["keystore"] = json!(keystore),
+                Err(e) => return send_error(e.as_ref()),
+            }
+        }
+        accounts.push(account);
+    }
+    let mut res_json = json!({"accounts": accounts});
+    if let Some(phrase) = &generated {
+        res_json["mnemonic"] = json!(phrase.as_str());
+    }
     send_response(
         200,
-        vec![(String::from("content-type"), String::from("text/html"))],
-        resp.into_bytes().to_vec(),
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
+    );
+}
+
+// The `message` parameter as bytes: UTF-8 text, or hex with `encoding=hex`.
+fn parse_message(params: &HashMap<String, Value>) -> Result<Vec<u8>, String> {
+    let message = params.get("message").ok_or("Require a message")?;
+    let message = message.as_str().map(str::to_string).unwrap_or(message.to_string());
+    match param(params, "encoding").as_deref() {
+        None | Some("utf8") => Ok(message.into_bytes()),
+        Some("hex") => hex::decode(message.trim_start_matches("0x")).map_err(|e| format!("Invalid hex message: {}", e)),
+        Some(encoding) => Err(format!("Invalid encoding: {}", encoding)),
+    }
+}
+
+// Signs `message` with the configured wallet as EIP-191 personal_sign does.
+async fn sign_message(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    let params = request_params(_qry, &_body);
+    log::info!("Sign message Query -- {:?}", redact_params(&params));
+
+    let chain = match select_chain(&params) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let message = match parse_message(&params) {
+        Ok(message) => message,
+        Err(e) => return send_bad_request(&e),
+    };
+    let from_index = match parse_from_index(&params) {
+        Ok(from_index) => from_index,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signer = match wallet::load_signer(chain.chain_id, from_index).await {
+        Ok(signer) => signer,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    let signature = match signer.sign_message(&message).await {
+        Ok(signature) => signature,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+    let res_json: Value = json!({"address": format!("{:?}", signer.address()), "signature": format!("0x{}", signature)});
+    send_response(
+        200,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
+    );
+}
+
+// Recovers the signer of the EIP-191 `signature` of `message`, which is valid when it is `address`.
+async fn verify_message(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    let params = request_params(_qry, &_body);
+    log::info!("Verify message Query -- {:?}", redact_params(&params));
+
+    let message = match parse_message(&params) {
+        Ok(message) => message,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signature = match param(&params, "signature").as_deref().map(Signature::from_str) {
+        Some(Ok(signature)) => signature,
+        Some(Err(e)) => return send_bad_request(&format!("Invalid signature: {}", e)),
+        None => return send_bad_request("Require a signature"),
+    };
+    let address = match param(&params, "address").as_deref().map(H160::from_str) {
+        Some(Ok(address)) => address,
+        Some(Err(e)) => return send_bad_request(&format!("Invalid address: {}", e)),
+        None => return send_bad_request("Require an address"),
+    };
+
+    let recovered = signature.recover(message).ok();
+    let res_json: Value = json!({
+        "address": recovered.map(|recovered| format!("{:?}", recovered)),
+        "valid": recovered == Some(address),
+    });
+    send_response(
+        200,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
     );
 }
 
 async fn send_transaction(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("Send trsaction Query -- {:?}", _qry);
+    log::info!("Send trsaction Query -- {:?}", redact_params(&_qry));
     
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let private_key = std::env::var("PRIVATE_KEY").unwrap_or("".to_string());
-    log::info!("ENV: {} {} {}", rpc_node_url, chain_id, private_key);
-    let wallet: LocalWallet = private_key
-    .parse::<LocalWallet>()
-    .unwrap()
-    .with_chain_id(chain_id);
-
-
-    let address_to = NameOrAddress::from(H160::from_str(_qry.get("address_to").expect("Require address_to").to_string().as_str().trim_matches('"')).expect("Failed to parse address_to"));
-    let mut value = U256::from_dec_str("0").unwrap();
-    if let Some(_value) = _qry.get("value") {
-        value = U256::from_dec_str(_qry.get("value").unwrap_or(&Value::Number(0.into())).as_str().unwrap().trim_matches('"')).expect("Failed to parse value.");
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let chain_id = chain.chain_id;
+    log::info!("ENV: {:?} {}", endpoints(&rpc_node_url).into_iter().map(redact_endpoint).collect::<Vec<_>>(), chain_id);
+    let from_index = match parse_from_index(&_qry) {
+        Ok(from_index) => from_index,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signer = match wallet::load_signer(chain_id, from_index).await {
+        Ok(signer) => signer,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+
+    let address_to = match parse_address(&_qry, "address_to") {
+        Ok(address) => NameOrAddress::from(address),
+        Err(e) => return send_bad_request(&e),
+    };
+    let mut value = U256::zero();
+    if let Some(qry_value) = param(&_qry, "value") {
+        value = match U256::from_dec_str(&qry_value) {
+            Ok(value) => value,
+            Err(_) => return send_bad_request(&format!("Invalid value: {}", qry_value)),
+        };
     }
     let mut data = Bytes::from(vec![0u8; 32]);
-    if let Some(qry_data) = _qry.get("data") {      
-        data = Bytes::from(hex::decode(qry_data.to_string().trim_matches('"').trim_start_matches("0x")).expect("Failed to parse data."));
+    if let Some(qry_data) = param(&_qry, "data") {
+        data = match hex::decode(qry_data.trim_start_matches("0x")) {
+            Ok(data) => Bytes::from(data),
+            Err(_) => return send_bad_request(&format!("Invalid data: {}", qry_data)),
+        };
     }
 
+    let tx_type = match param(&_qry, "type").map(|t| TxType::from_str(&t)).transpose() {
+        Ok(tx_type) => tx_type,
+        Err(e) => return send_bad_request(&e),
+    };
+
     log::info!("Parameter: {:#?} {:#?}", data, address_to);
 
-    let params = json!([wrap_transaction(&rpc_node_url, chain_id, wallet, address_to, data, value).await.unwrap().as_str()]);
-    let resp =json_rpc(&rpc_node_url, "eth_sendRawTransaction", params).await.expect("Failed to send raw transaction.");
+    let wait = match parse_wait_options(&_qry) {
+        Ok(wait) => wait,
+        Err(e) => return send_bad_request(&e),
+    };
+
+    let resp = match sign_and_send_transaction(&rpc_node_url, chain_id, signer.as_ref(), address_to, data, value, tx_type).await {
+        Ok(hash) => hash,
+        Err(e) => return send_error(e.as_ref()),
+    };
 
     log::info!("resp: {:#?}", resp);
 
-    send_response(
-        200,
-        vec![(String::from("content-type"), String::from("text/html"))],
-        resp.into_bytes().to_vec(),
-    );
+    match wait {
+        Some(wait) => send_receipt(&rpc_node_url, &resp, wait).await,
+        None => send_response(
+            200,
+            vec![(String::from("content-type"), String::from("text/html"))],
+            resp.into_bytes().to_vec(),
+        ),
+    }
 }
 
 async fn pbm_pay(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("PBM pay Query -- {:?}", _qry);
+    log::info!("PBM pay Query -- {:?}", redact_params(&_qry));
     
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let private_key = std::env::var("PRIVATE_KEY").unwrap_or("".to_string());
-    let wallet: LocalWallet = private_key
-    .parse::<LocalWallet>()
-    .unwrap()
-    .with_chain_id(chain_id);
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let chain_id = chain.chain_id;
+    let from_index = match parse_from_index(&_qry) {
+        Ok(from_index) => from_index,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signer = match wallet::load_signer(chain_id, from_index).await {
+        Ok(signer) => signer,
+        Err(e) => return send_error(e.as_ref()),
+    };
 
 
-    let reciver = NameOrAddress::from(H160::from_str(_qry.get("pay-to").expect("Require pay to address").to_string().as_str().trim_matches('"')).expect("Failed to parse address"));
-    let contract_addrss = NameOrAddress::from(H160::from_str(std::env::var("CONTRACT_ADDRESS").unwrap_or("0xb1C1cEE9952e99f1d114f80E6a17fD598Ef106Af".to_string()).as_str()).expect("Failed to parse contract address"));
+    let reciver = match parse_address(&_qry, "pay-to") {
+        Ok(address) => address,
+        Err(e) => return send_bad_request(&e),
+    };
+    let contract_addrss = match chain.contract("pbm") {
+        Some(address) => NameOrAddress::from(address),
+        None => return send_bad_request(&format!("No PBM contract on chain {}", chain_id)),
+    };
     let value = U256::from_dec_str("0").unwrap();
     let wei_to_eth = U256::from_dec_str("1000000000000000000").unwrap();
-    let data = create_contract_call_data("pay",
-     vec![Token::Address(reciver.as_address().unwrap().clone()), Token::Uint(U256::from(10) * wei_to_eth)])
-        .unwrap();
+    let data = match abi_registry::registry().encode_call("pbm", "pay", &[Token::Address(reciver), Token::Uint(U256::from(10) * wei_to_eth)]) {
+        Ok(data) => data,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    let tx_type = match param(&_qry, "type").map(|t| TxType::from_str(&t)).transpose() {
+        Ok(tx_type) => tx_type,
+        Err(e) => return send_bad_request(&e),
+    };
     log::info!("Parameter: {:#?} {:#?}", data, reciver);
 
-    let params = json!([wrap_transaction(&rpc_node_url, chain_id, wallet, contract_addrss, data, value).await.unwrap().as_str()]);
-    let resp =json_rpc(&rpc_node_url, "eth_sendRawTransaction", params).await.expect("Failed to send raw transaction.");
+    let wait = match parse_wait_options(&_qry) {
+        Ok(wait) => wait,
+        Err(e) => return send_bad_request(&e),
+    };
+
+    let resp = match sign_and_send_transaction(&rpc_node_url, chain_id, signer.as_ref(), contract_addrss, data, value, tx_type).await {
+        Ok(hash) => hash,
+        Err(e) => return send_error(e.as_ref()),
+    };
 
     log::info!("resp: {:#?}", resp);
 
+    match wait {
+        Some(wait) => send_receipt(&rpc_node_url, &resp, wait).await,
+        None => send_response(
+            200,
+            vec![(String::from("content-type"), String::from("text/html"))],
+            resp.into_bytes().to_vec(),
+        ),
+    }
+}
+
+// Query parameters, overridden by the fields of a JSON object body when one is posted.
+fn request_params(_qry: HashMap<String, Value>, _body: &[u8]) -> HashMap<String, Value> {
+    let mut params = _qry;
+    if let Ok(Value::Object(body)) = serde_json::from_slice::<Value>(_body) {
+        params.extend(body);
+    }
+    params
+}
+
+// Parameter `name` as a string, whether it came as a string or (from a JSON body) a number or bool.
+fn param(params: &HashMap<String, Value>, name: &str) -> Option<String> {
+    params.get(name).map(|v| v.as_str().map(str::to_string).unwrap_or(v.to_string())).map(|v| v.trim_matches('"').to_string())
+}
+
+// The `from_index` parameter: sign with that account of the configured HD wallet.
+fn parse_from_index(params: &HashMap<String, Value>) -> Result<Option<u32>, String> {
+    param(params, "from_index").map(|v| v.parse::<u32>().map_err(|_| format!("Invalid from_index: {}", v))).transpose()
+}
+
+// The address in parameter `name`.
+fn parse_address(params: &HashMap<String, Value>, name: &str) -> Result<H160, String> {
+    let address = param(params, name).ok_or(format!("Require {}", name))?;
+    H160::from_str(&address).map_err(|_| format!("Invalid {}: {}", name, address))
+}
+
+// The chain selected by the `chain` parameter (a chain id or name), or the default chain.
+fn select_chain(params: &HashMap<String, Value>) -> Result<&'static chains::ChainConfig, String> {
+    chains::chains().get(param(params, "chain").as_deref()).map_err(|e| e.to_string())
+}
+
+// Resolves `contract` (an address or a registered name), `function` (a name or signature) and
+// `args` (a JSON array, or its string form in a query) into the target address, function and tokens.
+fn parse_contract_request(chain: &chains::ChainConfig, params: &HashMap<String, Value>) -> Result<(H160, Function, Vec<Token>), String> {
+    let contract = param(params, "contract").ok_or("Require contract")?;
+    let function = param(params, "function").ok_or("Require function")?;
+    let address = match H160::from_str(&contract) {
+        Ok(address) => address,
+        Err(_) => chain
+            .contract(&contract)
+            .ok_or(format!("Contract {} has no address on chain {}", contract, chain.chain_id))?,
+    };
+    let function = abi_registry::registry().resolve_function(&contract, &function).map_err(|e| e.to_string())?;
+    let args = match params.get("args") {
+        Some(Value::String(args)) => serde_json::from_str::<Vec<Value>>(args).map_err(|e| format!("Invalid args: {}", e))?,
+        Some(Value::Array(args)) => args.clone(),
+        Some(other) => return Err(format!("Invalid args: {}", other)),
+        None => vec!(),
+    };
+    let kinds: Vec<_> = function.inputs.iter().map(|p| p.kind.clone()).collect();
+    let tokens = abi_registry::tokenize_json(&kinds, &args).map_err(|e| e.to_string())?;
+    Ok((address, function, tokens))
+}
+
+async fn contract_call(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("Contract call Query -- {:?}", redact_params(&_qry));
+
+    let params = request_params(_qry, &_body);
+    let chain = match select_chain(&params) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let (address, function, tokens) = match parse_contract_request(chain, &params) {
+        Ok(request) => request,
+        Err(e) => return send_bad_request(&e),
+    };
+    let from = param(&params, "from").unwrap_or("0x0000000000000000000000000000000000000000".to_string());
+
+    let outputs = match eth_call_function(&rpc_node_url, &from, format!("{:?}", address).as_str(), &function, &tokens).await {
+        Ok(outputs) => outputs,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+    let res_json: Value = json!({"outputs": abi_registry::outputs_to_json(&function, &outputs)});
     send_response(
         200,
-        vec![(String::from("content-type"), String::from("text/html"))],
-        resp.into_bytes().to_vec(),
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
     );
 }
 
+async fn contract_send(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("Contract send Query -- {:?}", redact_params(&_qry));
 
+    let params = request_params(_qry, &_body);
+    let chain = match select_chain(&params) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let chain_id = chain.chain_id;
+    let from_index = match parse_from_index(&params) {
+        Ok(from_index) => from_index,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signer = match wallet::load_signer(chain_id, from_index).await {
+        Ok(signer) => signer,
+        Err(e) => return send_error(e.as_ref()),
+    };
 
-pub async fn get_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    let (address, function, tokens) = match parse_contract_request(chain, &params) {
+        Ok(request) => request,
+        Err(e) => return send_bad_request(&e),
+    };
+    let data = match function.encode_input(&tokens) {
+        Ok(data) => Bytes::from(data),
+        Err(e) => return send_bad_request(&e.to_string()),
+    };
+    let value = match param(&params, "value").map(|v| U256::from_dec_str(&v)).transpose() {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => return send_bad_request(&format!("Invalid value: {}", e)),
+    };
+    let tx_type = match param(&params, "type").map(|t| TxType::from_str(&t)).transpose() {
+        Ok(tx_type) => tx_type,
+        Err(e) => return send_bad_request(&e),
+    };
+    let wait = match parse_wait_options(&params) {
+        Ok(wait) => wait,
+        Err(e) => return send_bad_request(&e),
+    };
+
+    log::info!("Parameter: {:#?} {:#?}", data, address);
+
+    let resp = match sign_and_send_transaction(&rpc_node_url, chain_id, signer.as_ref(), NameOrAddress::Address(address), data, value, tx_type).await {
+        Ok(hash) => hash,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+    log::info!("resp: {:#?}", resp);
+
+    match wait {
+        Some(wait) => send_receipt(&rpc_node_url, &resp, wait).await,
+        None => send_response(
+            200,
+            vec![(String::from("content-type"), String::from("text/html"))],
+            resp.into_bytes().to_vec(),
+        ),
+    }
+}
+
+async fn speed_up(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get txs Query -- {:?}", _qry);
-    
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let api_key = std::env::var("MORALIS_API_KEY").unwrap_or("".to_string());
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let caller = _qry.get("address").expect("Require an address").as_str().unwrap().trim_matches('"').to_string();
-    let eth_balance = get_ethbalance(&rpc_node_url, &caller).await.unwrap();
-    let mut transaction: Vec<Value> = vec!();
+    log::info!("Speed up Query -- {:?}", redact_params(&_qry));
+    replace_pending_transaction(_qry, false).await;
+}
 
-    match chain_id{
-        18 =>{
-            let query_tx = cmt_api::get_transaction(&caller).await.unwrap();
-            for idx in 0..query_tx.as_array().unwrap().len() {
-                if query_tx[idx]["from"].as_str().unwrap() == caller.to_lowercase() {
-                    transaction.push(query_tx[idx].clone());
-                } 
-            }
+async fn cancel(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("Cancel Query -- {:?}", redact_params(&_qry));
+    replace_pending_transaction(_qry, true).await;
+}
+
+async fn replace_pending_transaction(_qry: HashMap<String, Value>, cancel: bool) {
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let chain_id = chain.chain_id;
+    let from_index = match parse_from_index(&_qry) {
+        Ok(from_index) => from_index,
+        Err(e) => return send_bad_request(&e),
+    };
+    let signer = match wallet::load_signer(chain_id, from_index).await {
+        Ok(signer) => signer,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+    let hash = match param(&_qry, "hash") {
+        Some(hash) => hash,
+        None => return send_bad_request("Require hash"),
+    };
+    let wait = match parse_wait_options(&_qry) {
+        Ok(wait) => wait,
+        Err(e) => return send_bad_request(&e),
+    };
+
+    let original = match eth_get_tx_by_hash(&rpc_node_url, &hash).await {
+        Ok(tx) if tx.is_null() => return send_error_message(404, "Transaction not found"),
+        Ok(tx) => tx,
+        Err(e) => return send_rpc_error(&e),
+    };
+    let original: Transaction = match serde_json::from_value(original) {
+        Ok(tx) => tx,
+        Err(e) => return send_rpc_error(&e.into()),
+    };
+    if original.block_number.is_some() {
+        return send_error_message(409, "Transaction is already mined");
+    }
+    if original.from != signer.address() {
+        return send_error_message(403, "Transaction was not sent by the configured wallet");
+    }
+
+    let resp = match replace_transaction(&rpc_node_url, chain_id, signer.as_ref(), &original, cancel).await {
+        Ok(hash) => hash,
+        Err(e) => return send_error(e.as_ref()),
+    };
+
+    log::info!("resp: {:#?}", resp);
+
+    match wait {
+        Some(wait) => send_receipt(&rpc_node_url, &resp, wait).await,
+        None => send_response(
+            200,
+            vec![(String::from("content-type"), String::from("text/html"))],
+            resp.into_bytes().to_vec(),
+        ),
+    }
+}
+
+struct WaitOptions {
+    confirmations: u64,
+    timeout: Duration,
+}
+
+fn parse_wait_options(_qry: &HashMap<String, Value>) -> Result<Option<WaitOptions>, String> {
+    if param(_qry, "wait").as_deref() != Some("true") {
+        return Ok(None);
+    }
+    let confirmations = match param(_qry, "confirmations") {
+        Some(v) => v.parse::<u64>().map_err(|_| format!("Invalid confirmations: {}", v))?,
+        None => 1,
+    };
+    let timeout = match param(_qry, "timeout") {
+        Some(v) => v.parse::<u64>().map_err(|_| format!("Invalid timeout: {}", v))?,
+        None => 60,
+    };
+    Ok(Some(WaitOptions { confirmations: confirmations.max(1), timeout: Duration::from_secs(timeout) }))
+}
+
+async fn send_receipt(rpc_node_url: &str, hash: &str, wait: WaitOptions) {
+    let receipt = match wait_for_receipt(rpc_node_url, hash, wait.confirmations, wait.timeout).await {
+        Ok(receipt) => receipt,
+        Err(e) => return send_rpc_error(&e),
+    };
+    let (status, res_json) = match receipt {
+        Some(receipt) => (200, json!({
+            "transaction_hash": hash,
+            // Receipts before Byzantium, and on some chains still, carry no status.
+            "status": match receipt.status.map(|status| status.as_u64()) {
+                Some(1) => "success",
+                Some(_) => "failed",
+                None => "unknown",
+            },
+            "block_number": receipt.block_number.map(|n| n.as_u64()),
+            "gas_used": receipt.gas_used.map(|g| g.to_string()),
+            "effective_gas_price": receipt.effective_gas_price.map(|p| p.to_string()),
+            "confirmations": wait.confirmations,
+            "logs": receipt.logs.iter().map(|log| decode_registered_log(log).map(|decoded| decoded.to_json()).unwrap_or_else(|| json!(log))).collect::<Vec<Value>>(),
+        })),
+        None => (202, json!({"transaction_hash": hash, "status": "pending"})),
+    };
+    send_response(
+        status,
+        vec![(String::from("content-type"), String::from("application/json"))],
+        serde_json::to_vec_pretty(&res_json).unwrap(),
+    );
+}
+
+#[derive(PartialEq)]
+enum PaymentSort {
+    Block,
+    Timestamp,
+    Amount,
+}
+
+struct PaymentFilter {
+    counterparty: Option<H160>,
+    min_amount: Option<U256>,
+    max_amount: Option<U256>,
+    from_time: Option<u64>,
+    to_time: Option<u64>,
+    from_block: u64,
+    to_block: Option<u64>,
+    sort: PaymentSort,
+    descending: bool,
+    cursor: Option<LogCursor>,
+    limit: Option<usize>,
+}
+
+fn parse_payment_filter(_qry: &HashMap<String, Value>) -> Result<PaymentFilter, String> {
+    let number = |name: &str| param(_qry, name).map(|v| v.parse::<u64>().map_err(|_| format!("Invalid {}: {}", name, v))).transpose();
+    let amount = |name: &str| param(_qry, name).map(|v| U256::from_dec_str(&v).map_err(|_| format!("Invalid {}: {}", name, v))).transpose();
+    Ok(PaymentFilter {
+        counterparty: param(_qry, "counterparty").map(|v| H160::from_str(&v).map_err(|_| format!("Invalid counterparty: {}", v))).transpose()?,
+        min_amount: amount("min_amount")?,
+        max_amount: amount("max_amount")?,
+        from_time: number("from_time")?,
+        to_time: number("to_time")?,
+        from_block: number("from_block")?.unwrap_or(0),
+        to_block: number("to_block")?,
+        sort: match param(_qry, "sort").as_deref() {
+            None | Some("block") => PaymentSort::Block,
+            Some("timestamp") => PaymentSort::Timestamp,
+            Some("amount") => PaymentSort::Amount,
+            Some(v) => return Err(format!("Invalid sort: {}", v)),
         },
-        _ => {
-            let query_tx = moralis_api::get_transaction(&caller, &api_key, chain_id).await.unwrap();
-            for idx in 0..query_tx.as_array().unwrap().len() {
-                if query_tx[idx]["from_address"].as_str().unwrap() == caller.to_lowercase() {
-                    transaction.push(query_tx[idx].clone());
-                } 
-            }
+        descending: match param(_qry, "order").as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(v) => return Err(format!("Invalid order: {}", v)),
         },
+        cursor: param(_qry, "cursor").map(|v| LogCursor::from_str(&v)).transpose()?,
+        limit: number("limit")?.map(|limit| limit as usize),
+    })
+}
+
+impl PaymentFilter {
+    // PBM payments in the index where `address` is the `side` ("from" or "to") party, filtered and sorted.
+    fn select<'a>(&self, logs: &'a [IndexedLog], side: &str, counterparty_side: &str, address: H160) -> Vec<&'a IndexedLog> {
+        let in_range = |value: Option<U256>, min: Option<U256>, max: Option<U256>| {
+            value.is_some_and(|value| min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max))
+        };
+        let mut payments: Vec<&IndexedLog> = logs
+            .iter()
+            .filter(|log| log.event == "payEvent" && log.address_param(side) == Some(address))
+            .filter(|log| self.counterparty.is_none_or(|counterparty| log.address_param(counterparty_side) == Some(counterparty)))
+            .filter(|log| in_range(log.uint_param("amount"), self.min_amount, self.max_amount))
+            .filter(|log| in_range(log.uint_param("timestamp"), self.from_time.map(U256::from), self.to_time.map(U256::from)))
+            .filter(|log| log.block_number >= self.from_block && self.to_block.is_none_or(|to_block| log.block_number <= to_block))
+            .collect();
+        match self.sort {
+            PaymentSort::Block => payments.sort_by_key(|log| (log.block_number, log.log_index)),
+            PaymentSort::Timestamp => payments.sort_by_key(|log| (log.uint_param("timestamp"), log.block_number, log.log_index)),
+            PaymentSort::Amount => payments.sort_by_key(|log| (log.uint_param("amount"), log.block_number, log.log_index)),
+        }
+        if self.descending {
+            payments.reverse();
+        }
+        payments
+    }
+
+    // The page of `payments` from `cursor`, which names the first payment of the page, and the cursor of
+    // the payment after it.
+    fn page<'a>(&self, payments: &'a [&'a IndexedLog]) -> Result<(&'a [&'a IndexedLog], Option<LogCursor>), String> {
+        let start = match self.cursor {
+            None => 0,
+            Some(cursor) => match payments.iter().position(|log| log.cursor() == cursor) {
+                Some(start) => start,
+                // In block order the page starts at the same place even if that payment is gone, say after a reorg.
+                None if self.sort == PaymentSort::Block => payments
+                    .iter()
+                    .position(|log| if self.descending { log.cursor() < cursor } else { log.cursor() > cursor })
+                    .unwrap_or(payments.len()),
+                None => return Err(format!("Cursor {} is not a payment of this query", cursor)),
+            },
+        };
+        let end = self.limit.map_or(payments.len(), |limit| payments.len().min(start.saturating_add(limit)));
+        Ok((&payments[start..end], payments.get(end).map(|log| log.cursor())))
     }
-    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": eth_balance.to_string()});
+}
+
+// PBM payments where `address` is the `side` ("from" or "to") party in the block range of `filter`, read
+// straight from the node. Returns them with the last block scanned.
+async fn scan_payments(rpc_node_url: &str, contract_address: H160, side: &str, address: H160, filter: &PaymentFilter) -> Result<(Vec<IndexedLog>, u64), Box<dyn std::error::Error + Send + Sync>> {
+    let pay_event = abi_registry::registry().event("pbm", "payEvent")?;
+    let to_block = match filter.to_block {
+        Some(to_block) => to_block,
+        None => get_block_number(rpc_node_url).await?,
+    };
+    let (account, counterparty) = (Some(H256::from(address)), filter.counterparty.map(H256::from));
+    let topics = match side {
+        "from" => json!([event_topic(pay_event), account, counterparty]),
+        _ => json!([event_topic(pay_event), counterparty, account]),
+    };
+    let logs = scan_logs(rpc_node_url, Some(&format!("{:?}", contract_address)), topics, filter.from_block, Some(to_block)).await?;
+    Ok((logs.iter().filter_map(IndexedLog::decode).collect(), to_block))
+}
+
+fn parse_history_query(_qry: &HashMap<String, Value>) -> Result<indexer_backend::HistoryQuery, String> {
+    let date = |name: &str| param(_qry, name).map(|v| indexer_backend::parse_date(&v).ok_or(format!("Invalid {}: {}", name, v))).transpose();
+    Ok(indexer_backend::HistoryQuery {
+        cursor: param(_qry, "cursor"),
+        limit: param(_qry, "limit").map(|v| v.parse::<usize>().ok().filter(|limit| *limit > 0).ok_or(format!("Invalid limit: {}", v))).transpose()?,
+        from_date: date("from_date")?,
+        to_date: date("to_date")?,
+    })
+}
+
+pub async fn get_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("get txs Query -- {:?}", redact_params(&_qry));
+    
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let caller = match parse_address(&_qry, "address") {
+        Ok(address) => format!("{:?}", address),
+        Err(e) => return send_bad_request(&e),
+    };
+    let eth_balance = match get_ethbalance(&rpc_node_url, &caller).await {
+        Ok(balance) => balance,
+        Err(e) => return send_rpc_error(&e),
+    };
+    let backend = chain.backend();
+    let query = match parse_history_query(&_qry) {
+        Ok(query) => query,
+        Err(e) => return send_bad_request(&e),
+    };
+    let page = match backend.transactions(&caller, &query).await {
+        Ok(page) => page,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    let transaction = page.items.into_iter().filter(|tx| tx.from == caller).collect::<Vec<_>>();
+    let res_json:Value = json!({"transaction": transaction, "balance": eth_balance.to_string(), "symbol": chain.native_symbol, "decimals": chain.native_decimals, "next_cursor": page.next_cursor});
     send_response(
         200,
         vec![(String::from("content-type"), String::from("application/json"))],
@@ -250,12 +981,22 @@ pub async fn get_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Valu
 
 pub async fn get_balance(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get balance Query -- {:?}", _qry);
+    log::info!("get balance Query -- {:?}", redact_params(&_qry));
     
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let caller = H160::from_str(_qry.get("address").expect("Require an address").to_string().as_str().trim_matches('"')).expect("Failed to parse address");
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let caller = match parse_address(&_qry, "address") {
+        Ok(address) => address,
+        Err(e) => return send_bad_request(&e),
+    };
     
-    let resp = get_ethbalance(&rpc_node_url, format!("{:?}", caller).as_str()).await.unwrap().to_string();
+    let resp = match get_ethbalance(&rpc_node_url, format!("{:?}", caller).as_str()).await {
+        Ok(balance) => balance.to_string(),
+        Err(e) => return send_rpc_error(&e),
+    };
 
     send_response(
         200,
@@ -266,21 +1007,26 @@ pub async fn get_balance(_headers: Vec<(String, String)>, _qry: HashMap<String,
 
 pub async fn get_pbm_balance(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get pbm balance Query -- {:?}", _qry);
+    log::info!("get pbm balance Query -- {:?}", redact_params(&_qry));
     
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let contract_addrss = H160::from_str(std::env::var("CONTRACT_ADDRESS").unwrap_or("0xb1C1cEE9952e99f1d114f80E6a17fD598Ef106Af".to_string()).as_str()).expect("Failed to parse contract address");
-    let caller = H160::from_str(_qry.get("address").expect("Require an address").to_string().as_str().trim_matches('"')).expect("Failed to parse address");
-
-    let data = create_contract_call_data("balanceOf", vec![Token::Address(caller.clone())]).unwrap();
-    let resp = U256::from_str(
-        eth_call(&rpc_node_url, "0x0000000000000000000000000000000000000000", format!("{:?}", contract_addrss).as_str(), format!("{:}", data).as_str())
-        .await
-        .unwrap()
-        .as_str()
-        )
-        .unwrap()
-        .to_string();
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let contract_addrss = match chain.contract("pbm") {
+        Some(address) => address,
+        None => return send_bad_request(&format!("No PBM contract on chain {}", chain.chain_id)),
+    };
+    let caller = match parse_address(&_qry, "address") {
+        Ok(address) => address,
+        Err(e) => return send_bad_request(&e),
+    };
+
+    let resp = match pbm_balance(&rpc_node_url, contract_addrss, caller).await {
+        Ok(balance) => balance.as_str().unwrap_or_default().to_string(),
+        Err(e) => return send_error(e.as_ref()),
+    };
 
     send_response(
         200,
@@ -289,35 +1035,82 @@ pub async fn get_pbm_balance(_headers: Vec<(String, String)>, _qry: HashMap<Stri
     );
 }
 
+// The PBM balance of `address` from balanceOf, as a decimal string. The PBM ABI may come from ABI_REGISTRY,
+// so a missing function is an error rather than a given.
+async fn pbm_balance(rpc_node_url: &str, contract_address: H160, address: H160) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let balance_of = abi_registry::registry().function("pbm", "balanceOf")?;
+    let outputs = eth_call_function(rpc_node_url, "0x0000000000000000000000000000000000000000", &format!("{:?}", contract_address), balance_of, &[Token::Address(address)]).await?;
+    Ok(abi_registry::token_to_json(outputs.first().ok_or("balanceOf returned no value")?))
+}
+
 pub async fn get_pbm_from_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get pbm from txs Query -- {:?}", _qry);
-    
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let contract_addrss = std::env::var("CONTRACT_ADDRESS").unwrap_or("0xb1C1cEE9952e99f1d114f80E6a17fD598Ef106Af".to_string()).to_string();
-    let query_address = H160::from_str(_qry.get("address").expect("Require an address").to_string().as_str().trim_matches('"')).expect("Failed to parse address");
-    let data = create_contract_call_data("balanceOf", vec![Token::Address(query_address.clone())]).unwrap();
-    let balance = U256::from_str(eth_call(&rpc_node_url, "0x0000000000000000000000000000000000000000", format!("{:?}", contract_addrss).as_str().trim_matches('"'), format!("{:}", data).as_str()).await.unwrap().as_str()).unwrap().to_string();
-    let mut bytes = vec![0u8; 32];
-    bytes[12..32].copy_from_slice(&query_address.0);
-    let data = Bytes::from(bytes);
-    // Keccak-256 payEvent(address,address,uint256)
-    let log = get_log(&rpc_node_url, &contract_addrss, json!(["0x34882e90c95bfeaeb7e0738cfd8af3d1f6ab3d2065dd70f6660b404b9beb3505", format!("{:}", data).as_str()])).await.unwrap();
+    log::info!("get pbm from txs Query -- {:?}", redact_params(&_qry));
+
+    send_pbm_payments(_qry, "from", "to").await;
+}
+
+pub async fn get_pbm_to_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("get pbm to txs Query -- {:?}", redact_params(&_qry));
+
+    send_pbm_payments(_qry, "to", "from").await;
+}
+
+async fn send_pbm_payments(_qry: HashMap<String, Value>, side: &str, counterparty_side: &str) {
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let rpc_node_url = chain.rpc_node_url.clone();
+    let contract_address = match chain.contract("pbm") {
+        Some(address) => address,
+        None => return send_bad_request(&format!("No PBM contract on chain {}", chain.chain_id)),
+    };
+    let query_address = match parse_address(&_qry, "address") {
+        Ok(address) => address,
+        Err(e) => return send_bad_request(&e),
+    };
+    let filter = match parse_payment_filter(&_qry) {
+        Ok(filter) => filter,
+        Err(e) => return send_bad_request(&e),
+    };
+    let balance = match pbm_balance(&rpc_node_url, contract_address, query_address).await {
+        Ok(balance) => balance,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    // The index pays off only when it is kept between requests, otherwise just the requested blocks are scanned.
+    let scanned = match log_indexer() {
+        Some(indexer) => indexer.sync(&rpc_node_url, chain.chain_id, contract_address).await.map(|index| (index.logs, index.last_block.unwrap_or_default())),
+        None => scan_payments(&rpc_node_url, contract_address, side, query_address, &filter).await,
+    };
+    let (logs, last_block) = match scanned {
+        Ok(scanned) => scanned,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    let payments = filter.select(&logs, side, counterparty_side, query_address);
+    let (page, next_cursor) = match filter.page(&payments) {
+        Ok(page) => page,
+        Err(e) => return send_bad_request(&e),
+    };
+
     let mut transaction: Vec<Value> = vec!();
-    let len = log.as_array().unwrap().len();
-    for idx in 0..len{
-        let now = log.get(idx).unwrap();
-        let pay_transaction = eth_get_tx_by_hash(&rpc_node_url, now["transactionHash"].as_str().unwrap()).await.unwrap();
+    let hashes: Vec<String> = page.iter().map(|now| format!("{:?}", now.transaction_hash)).collect();
+    let pay_transactions = match eth_get_txs_by_hash(&rpc_node_url, &hashes.iter().map(String::as_str).collect::<Vec<&str>>()).await {
+        Ok(txs) => txs,
+        Err(e) => return send_rpc_error(&e),
+    };
+    for (pay, pay_transaction) in page.iter().zip(pay_transactions) {
         let new_vec = json!({
-            "timestamp":U256::from_str(&now["data"].as_str().unwrap()[0..66]).unwrap().to_string(),
-            "from": format!("0x{}", &(now["topics"][1].to_string()).trim_matches('"')[26..]),
-            "to": format!("0x{}", &(now["topics"][2].to_string()).trim_matches('"')[26..]),
-            "amount": U256::from_str(&now["data"].as_str().unwrap()[66..130]).unwrap().to_string(),
+            "timestamp": pay.params.get("timestamp"),
+            "from": pay.params.get("from"),
+            "to": pay.params.get("to"),
+            "amount": pay.params.get("amount"),
             "transaction_detail": pay_transaction,
         });
         transaction.push(new_vec);
     } 
-    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": balance});
+    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": balance, "next_cursor": next_cursor.map(|c| c.to_string()), "indexed_block": last_block});
     
     send_response(
         200,
@@ -326,35 +1119,23 @@ pub async fn get_pbm_from_txs(_headers: Vec<(String, String)>, _qry: HashMap<Str
     );
 }
 
-pub async fn get_pbm_to_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+pub async fn get_erc20_balance(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get pbm to txs Query -- {:?}", _qry);
+    log::info!("get erc20 balance Query -- {:?}", redact_params(&_qry));
     
-    let rpc_node_url = std::env::var("RPC_NODE_URL").unwrap_or("https://mainnet.cybermiles.io".to_string());
-    let contract_addrss = std::env::var("CONTRACT_ADDRESS").unwrap_or("0xb1C1cEE9952e99f1d114f80E6a17fD598Ef106Af".to_string()).to_string();
-    let query_address = H160::from_str(_qry.get("address").expect("Require an address").to_string().as_str().trim_matches('"')).expect("Failed to parse address");
-    let data = create_contract_call_data("balanceOf", vec![Token::Address(query_address.clone())]).unwrap();
-    let balance = U256::from_str(eth_call(&rpc_node_url, "0x0000000000000000000000000000000000000000", format!("{:?}", contract_addrss).as_str().trim_matches('"'), format!("{:}", data).as_str()).await.unwrap().as_str()).unwrap().to_string();
-    let mut bytes = vec![0u8; 32];
-    bytes[12..32].copy_from_slice(&query_address.0);
-    let data = Bytes::from(bytes);
-    // Keccak-256 payEvent(uint256,address,address,uint256)
-    let log = get_log(&rpc_node_url, &contract_addrss, json!(["0x34882e90c95bfeaeb7e0738cfd8af3d1f6ab3d2065dd70f6660b404b9beb3505", null, format!("{:}", data).as_str()])).await.unwrap();
-    let mut transaction: Vec<Value> = vec!();
-    let len = log.as_array().unwrap().len();
-    for idx in 0..len{
-        let now = log.get(idx).unwrap();
-        let pay_transaction = eth_get_tx_by_hash(&rpc_node_url, now["transactionHash"].as_str().unwrap()).await.unwrap();
-        let new_vec = json!({
-            "timestamp":U256::from_str(&now["data"].as_str().unwrap()[0..66]).unwrap().to_string(),
-            "from": format!("0x{}", &(now["topics"][1].to_string()).trim_matches('"')[26..]),
-            "to": format!("0x{}", &(now["topics"][2].to_string()).trim_matches('"')[26..]),
-            "amount": U256::from_str(&now["data"].as_str().unwrap()[66..130]).unwrap().to_string(),
-            "transaction_detail": pay_transaction,
-        });
-        transaction.push(new_vec);
-    } 
-    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": balance});
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let query_address = match parse_address(&_qry, "address") {
+        Ok(address) => format!("{:?}", address),
+        Err(e) => return send_bad_request(&e),
+    };
+    let backend = chain.backend();
+    let res_json: Value = match backend.token_balances(&query_address).await {
+        Ok(balances) => json!(balances),
+        Err(e) => return send_error(e.as_ref()),
+    };
     
     send_response(
         200,
@@ -363,24 +1144,41 @@ pub async fn get_pbm_to_txs(_headers: Vec<(String, String)>, _qry: HashMap<Strin
     );
 }
 
-pub async fn get_erc20_balance(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+pub async fn get_erc20_from_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get erc20 balance Query -- {:?}", _qry);
-    
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let api_key = std::env::var("MORALIS_API_KEY").unwrap_or("".to_string());
-    let query_address = _qry.get("address").expect("Require an address").as_str().unwrap().trim_matches('"').to_string();
-    let res_json:Value;
+    log::info!("get erc20 from txs Query -- {:?}", redact_params(&_qry));
+
+    send_token_transfers(_qry, true).await;
+}
+
+pub async fn get_erc20_to_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+    logger::init();
+    log::info!("get erc20 to txs Query -- {:?}", redact_params(&_qry));
+
+    send_token_transfers(_qry, false).await;
+}
+
+async fn send_token_transfers(_qry: HashMap<String, Value>, outgoing: bool) {
+    let chain = match select_chain(&_qry) {
+        Ok(chain) => chain,
+        Err(e) => return send_bad_request(&e),
+    };
+    let query_address = match parse_address(&_qry, "address") {
+        Ok(address) => format!("{:?}", address),
+        Err(e) => return send_bad_request(&e),
+    };
+    let backend = chain.backend();
+    let query = match parse_history_query(&_qry) {
+        Ok(query) => query,
+        Err(e) => return send_bad_request(&e),
+    };
+    let (page, balance) = match backend.token_transfers_and_balances(&query_address, &query).await {
+        Ok(history) => history,
+        Err(e) => return send_error(e.as_ref()),
+    };
+    let transaction = page.items.into_iter().filter(|t| if outgoing { t.from == query_address } else { t.to == query_address }).collect::<Vec<_>>();
     
-    match chain_id{
-        18 =>{
-            res_json = cmt_api::get_erc20_balance(&query_address).await.unwrap();
-            
-        },
-        _ => {
-            res_json = moralis_api::get_erc20_balance(&query_address, &api_key, chain_id).await.unwrap();
-        },
-    }
+    let res_json:Value = json!({"transaction": transaction, "balance": balance, "next_cursor": page.next_cursor});
     
     send_response(
         200,
@@ -389,39 +1187,12 @@ pub async fn get_erc20_balance(_headers: Vec<(String, String)>, _qry: HashMap<St
     );
 }
 
-pub async fn get_erc20_from_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+async fn cache_metrics(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get erc20 from txs Query -- {:?}", _qry);
-    
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let api_key = std::env::var("MORALIS_API_KEY").unwrap_or("".to_string());
-    let query_address = _qry.get("address").expect("Require an address").as_str().unwrap().trim_matches('"').to_string();
-    let mut transaction: Vec<Value> = vec!();
-    let balance: Value;
-
-    match chain_id{
-        18 =>{
-            let txs = cmt_api::get_erc20_transfer(&query_address).await.unwrap();
-            for idx in 0..txs.as_array().unwrap().len() {
-                if txs[idx]["from"].as_str().unwrap() == query_address.to_lowercase() {
-                    transaction.push(txs[idx].clone());
-                } 
-            }
-            balance = cmt_api::get_erc20_balance(&query_address).await.unwrap();
-        },
-        _ => {
-            let txs = moralis_api::get_erc20_transfer(&query_address,&api_key, chain_id).await.unwrap();
-            for idx in 0..txs.as_array().unwrap().len() {
-                if txs[idx]["from_address"].as_str().unwrap() == query_address.to_lowercase() {
-                    transaction.push(txs[idx].clone());
-                } 
-            }
-            balance = moralis_api::get_erc20_balance(&query_address, &api_key, chain_id).await.unwrap();
-        },
-    }
-    
-    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": balance});
-    
+
+    let stats = cache::cache().stats();
+    let (hits, misses) = stats.values().fold((0, 0), |(hits, misses), s| (hits + s.hits, misses + s.misses));
+    let res_json: Value = json!({"hits": hits, "misses": misses, "methods": stats});
     send_response(
         200,
         vec![(String::from("content-type"), String::from("application/json"))],
@@ -429,42 +1200,89 @@ pub async fn get_erc20_from_txs(_headers: Vec<(String, String)>, _qry: HashMap<S
     );
 }
 
-pub async fn get_erc20_to_txs(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
+async fn rpc_health(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>, _body: Vec<u8>){
     logger::init();
-    log::info!("get erc20 to txs Query -- {:?}", _qry);
-    
-    let chain_id = std::env::var("CHAIN_ID").unwrap_or("18".to_string()).parse::<u64>().unwrap_or(18u64);
-    let api_key = std::env::var("MORALIS_API_KEY").unwrap_or("".to_string());
-    let query_address = _qry.get("address").expect("Require an address").as_str().unwrap().trim_matches('"').to_string();
-    let mut transaction: Vec<Value> = vec!();
-    let balance: Value;
-
-    match chain_id{
-        18 =>{
-            let txs = cmt_api::get_erc20_transfer(&query_address).await.unwrap();
-            for idx in 0..txs.as_array().unwrap().len() {
-                if txs[idx]["to"].as_str().unwrap() == query_address.to_lowercase() {
-                    transaction.push(txs[idx].clone());
-                } 
-            }
-            balance = cmt_api::get_erc20_balance(&query_address).await.unwrap();
-        },
-        _ => {
-            let txs = moralis_api::get_erc20_transfer(&query_address,&api_key, chain_id).await.unwrap();
-            for idx in 0..txs.as_array().unwrap().len() {
-                if txs[idx]["to_address"].as_str().unwrap() == query_address.to_lowercase() {
-                    transaction.push(txs[idx].clone());
-                } 
-            }
-            balance = moralis_api::get_erc20_balance(&query_address, &api_key, chain_id).await.unwrap();
-        },
-    }
-    
-    let res_json:Value = json!({"transaction":Into::<Value>::into(transaction), "balance": balance});
-    
+
+    let registry = endpoint_registry();
+    let res_json: Value = chains::chains().all().iter().map(|chain| json!({
+        "chain_id": chain.chain_id,
+        "name": chain.name,
+        "endpoints": endpoints(&chain.rpc_node_url).into_iter().map(|endpoint| registry.status(endpoint)).collect::<Vec<_>>(),
+    })).collect();
     send_response(
         200,
         vec![(String::from("content-type"), String::from("application/json"))],
         serde_json::to_vec_pretty(&res_json).unwrap(),
     );
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGS.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn gen_key_never_logs_secrets() {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let phrase = "test test test test test test test test test test test junk";
+        let password = "correct horse battery staple";
+        let params = HashMap::from([
+            ("phrase".to_string(), json!(phrase)),
+            ("export".to_string(), json!("true")),
+            ("password".to_string(), json!(password)),
+        ]);
+        let (_, body) = gen_key_response(&params).unwrap();
+        let imported: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(imported["address"], "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        assert!(imported["keystore"].is_object());
+        assert!(imported.get("mnemonic").is_none());
+
+        let params = HashMap::from([("export".to_string(), json!("true"))]);
+        let (_, body) = gen_key_response(&params).unwrap();
+        let generated: Value = serde_json::from_slice(&body).unwrap();
+        let mnemonic = generated["mnemonic"].as_str().unwrap().to_string();
+        let path = generated["path"].as_str().unwrap().to_string();
+        let generated_wallet = wallet::derive_wallets(&mnemonic, wallet::Language::English, None, &[path]).unwrap().remove(0);
+
+        let secrets = [
+            phrase.to_string(),
+            password.to_string(),
+            mnemonic,
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string(),
+            hex::encode(generated_wallet.signer().to_bytes()),
+        ];
+        let logs = LOGS.lock().unwrap();
+        assert!(logs.iter().any(|line| line.contains("[redacted]")));
+        for line in logs.iter() {
+            for secret in &secrets {
+                assert!(!line.contains(secret.as_str()), "secret logged: {}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_wait_options_from_json_body() {
+        let params = request_params(HashMap::new(), br#"{"wait": true, "confirmations": 3, "timeout": "90"}"#);
+        let wait = parse_wait_options(&params).unwrap().unwrap();
+        assert_eq!((wait.confirmations, wait.timeout), (3, Duration::from_secs(90)));
+        assert!(parse_wait_options(&request_params(HashMap::new(), br#"{"wait": false}"#)).unwrap().is_none());
+    }
+}
@@ -1,5 +1,4 @@
 use serde_json::Value;
-use serde_json::json;
 // use std::time::Duration;
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 use http_req::{
@@ -7,43 +6,141 @@ use http_req::{
     uri::Uri,
 };
 use std::convert::TryFrom;
+use crate::cache::{cache, Ttl};
+use crate::indexer_backend::{json_address, json_string, json_u64, parse_date, BackendFuture, HistoryPage, HistoryQuery, IndexerBackend, TokenBalance, TokenTransfer, Transaction};
 
-pub async fn get_transaction(address: &str, api_key: &str, chain_id: u64) -> Result<Value> {
-	let result = get_request(format!("{}?chain={:#x}", address, chain_id).as_str(), api_key)
-	.await
-	.unwrap();
-	Ok(result)
+// Largest page Moralis serves.
+const MORALIS_PAGE_SIZE: usize = 100;
+
+pub struct MoralisApi {
+	api_key: String,
+	chain_id: u64,
 }
 
-pub async fn get_erc20_balance(address: &str, api_key: &str, chain_id: u64) -> Result<Value> {
-	let result = get_request(format!("{}/erc20?chain={:#x}", address, chain_id).as_str(), api_key)
-	.await
-	.unwrap();
-	let mut balance: Vec<Value> = vec!();
-	for idx in 0..result.as_array().unwrap().len() {
-		let new_vec = json!({
-			"name": result[idx]["name"],
-			"symbol": result[idx]["symbol"],
-			"balance": result[idx]["balance"],
-			"contractAddress": result[idx]["token_address"],
-			"decimals": result[idx]["decimals"],
-		});
-		balance.push(new_vec);
+impl MoralisApi {
+	pub fn new(api_key: &str, chain_id: u64) -> Self {
+		MoralisApi { api_key: api_key.to_string(), chain_id }
+	}
+
+	pub async fn get_transaction(&self, address: &str, query: &HistoryQuery) -> Result<(Vec<Value>, Option<String>)> {
+		self.get_pages(address, query).await
+	}
+
+	pub async fn get_erc20_balance(&self, address: &str) -> Result<Value> {
+		get_request(format!("{}/erc20?chain={:#x}", address, self.chain_id).as_str(), &self.api_key).await
+	}
+
+	pub async fn get_erc20_transfer(&self, address: &str, query: &HistoryQuery) -> Result<(Vec<Value>, Option<String>)> {
+		self.get_pages(format!("{}/erc20/transfers", address).as_str(), query).await
+	}
+
+	// Follows the Moralis cursor from `query.cursor` until `query.limit` items are collected, the history
+	// ends, or MORALIS_MAX_PAGES pages (default 10) have been read. Returns the items and the cursor to
+	// continue from.
+	async fn get_pages(&self, path: &str, query: &HistoryQuery) -> Result<(Vec<Value>, Option<String>)> {
+		let max_pages = std::env::var("MORALIS_MAX_PAGES").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(10).max(1);
+		let mut items: Vec<Value> = vec!();
+		let mut cursor = query.cursor.clone();
+		for _ in 0..max_pages {
+			let mut request = format!("{}?chain={:#x}", path, self.chain_id);
+			if let Some(limit) = query.limit {
+				request += &format!("&limit={}", (limit - items.len()).min(MORALIS_PAGE_SIZE));
+			}
+			if let Some(from_date) = query.from_date {
+				request += &format!("&from_date={}", from_date);
+			}
+			if let Some(to_date) = query.to_date {
+				request += &format!("&to_date={}", to_date);
+			}
+			if let Some(cursor) = &cursor {
+				request += &format!("&cursor={}", cursor);
+			}
+			let page = get_json(&request, &self.api_key).await?;
+			items.extend(result_items(&page["result"])?.iter().cloned());
+			cursor = json_string(&page["cursor"]);
+			if cursor.is_none() || query.limit.is_some_and(|limit| items.len() >= limit) {
+				break;
+			}
+		}
+		Ok((items, cursor))
 	}
+}
 
-	Ok(balance.into())
+fn result_items(result: &Value) -> Result<&Vec<Value>> {
+	result.as_array().ok_or_else(|| format!("Moralis returned {}", result).into())
 }
 
-pub async fn get_erc20_transfer(address: &str, api_key: &str, chain_id: u64) -> Result<Value> {
-	println!("get_erc20_transfer: {} {} {}", address, api_key, chain_id);
-	let result = get_request(format!("{}/erc20/transfers?chain={:#x}", address, chain_id).as_str(), api_key)
-	.await
-	.unwrap();
-	Ok(result)
+impl IndexerBackend for MoralisApi {
+	fn transactions<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<Transaction>> {
+		Box::pin(async move {
+			let (items, next_cursor) = self.get_transaction(address, query).await?;
+			let items = items.iter().map(|tx| Transaction {
+				hash: json_string(&tx["hash"]).unwrap_or_default(),
+				block_number: json_u64(&tx["block_number"]),
+				timestamp: tx["block_timestamp"].as_str().and_then(parse_date),
+				from: json_address(&tx["from_address"]).unwrap_or_default(),
+				to: json_address(&tx["to_address"]),
+				value: json_string(&tx["value"]).unwrap_or("0".to_string()),
+				success: json_string(&tx["receipt_status"]).map(|status| status == "1"),
+			}).collect();
+			Ok(HistoryPage { items, next_cursor })
+		})
+	}
+
+	fn token_transfers<'a>(&'a self, address: &'a str, query: &'a HistoryQuery) -> BackendFuture<'a, HistoryPage<TokenTransfer>> {
+		Box::pin(async move {
+			let (items, next_cursor) = self.get_erc20_transfer(address, query).await?;
+			let items = items.iter().map(|tx| TokenTransfer {
+				hash: json_string(&tx["transaction_hash"]).unwrap_or_default(),
+				block_number: json_u64(&tx["block_number"]),
+				timestamp: tx["block_timestamp"].as_str().and_then(parse_date),
+				token_address: json_address(&tx["address"]).unwrap_or_default(),
+				token_name: json_string(&tx["token_name"]),
+				token_symbol: json_string(&tx["token_symbol"]),
+				token_decimals: json_u64(&tx["token_decimals"]).map(|d| d as u32),
+				from: json_address(&tx["from_address"]).unwrap_or_default(),
+				to: json_address(&tx["to_address"]).unwrap_or_default(),
+				value: json_string(&tx["value"]).unwrap_or("0".to_string()),
+			}).collect();
+			Ok(HistoryPage { items, next_cursor })
+		})
+	}
+
+	fn token_balances<'a>(&'a self, address: &'a str) -> BackendFuture<'a, Vec<TokenBalance>> {
+		Box::pin(async move {
+			let result = self.get_erc20_balance(address).await?;
+			Ok(result_items(&result)?.iter().map(|token| TokenBalance {
+				token_address: json_address(&token["token_address"]).unwrap_or_default(),
+				name: json_string(&token["name"]),
+				symbol: json_string(&token["symbol"]),
+				decimals: json_u64(&token["decimals"]).map(|d| d as u32),
+				balance: json_string(&token["balance"]).unwrap_or("0".to_string()),
+			}).collect())
+		})
+	}
 }
 
 async fn get_request(query: &str, api_key: &str) -> Result<Value> {
-	log::info!("get_request: {} {}", query, api_key);
+	let res_json = get_json(query, api_key).await?;
+	// let map: HashMap<String, serde_json::Value> = res_json; 
+	
+	if res_json.is_object() && res_json.get("result").is_some() {
+		Ok(res_json.get("result").unwrap().clone())
+	} else {
+		Ok(res_json)
+	}
+}
+
+// Cached for 30 seconds unless CACHE_TTLS sets "moralis". Error responses, which carry only a message,
+// are not cached.
+async fn get_json(query: &str, api_key: &str) -> Result<Value> {
+	let ttl = cache().ttl("moralis", Ttl::Seconds(30));
+	let is_error = |res_json: &Value| res_json.get("message").is_some() && res_json.get("result").is_none();
+	cache().get_or_fetch("moralis", query, ttl, fetch_json(query, api_key), |res_json| !is_error(res_json)).await
+}
+
+async fn fetch_json(query: &str, api_key: &str) -> Result<Value> {
+	log::info!("get_request: {}", query);
 	// let client = reqwest::Client::new();
 	// let res = client
 	// 	.get(url + query)
@@ -60,7 +157,7 @@ async fn get_request(query: &str, api_key: &str) -> Result<Value> {
 	// 	}
 	// };
 	let url = format!("https://deep-index.moralis.io/api/v2.2/{}", query);
-	let addr = Uri::try_from(url.as_str()).unwrap();
+	let addr = Uri::try_from(url.as_str())?;
     let mut writer:Vec<u8> = Vec::new();
 
     Request::new(&addr)
@@ -68,15 +165,7 @@ async fn get_request(query: &str, api_key: &str) -> Result<Value> {
         // .header("Connection", "Close")
         .header("accept", "application/json")
         .header("X-API-Key", api_key)
-        .send(&mut writer)
-        .unwrap();
+        .send(&mut writer)?;
     let body = std::str::from_utf8(&writer)?;
-	let res_json: Value = serde_json::from_str(body)?;
-	// let map: HashMap<String, serde_json::Value> = res_json; 
-	
-	if res_json.is_object() && res_json.get("result") != None {
-		Ok(res_json.get("result").unwrap().clone())
-	} else {
-		Ok(res_json)
-	}
+	Ok(serde_json::from_str(body)?)
 } 
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+use ethers_core::k256::ecdsa::SigningKey;
+use ethers_core::rand;
+use ethers_signers::LocalWallet;
+use ethers_signers::coins_bip39::{ChineseSimplified, ChineseTraditional, Czech, English, French, Italian, Japanese, Korean, Mnemonic, Portuguese, Spanish, Wordlist};
+use zeroize::Zeroizing;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Accounts are derived at DEFAULT_HD_PATH/index unless another base path is given.
+pub const DEFAULT_HD_PATH: &str = "m/44'/60'/0'/0";
+
+// Most accounts derived in one request.
+pub const MAX_HD_ACCOUNTS: u32 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+	#[default]
+	English,
+	ChineseSimplified,
+	ChineseTraditional,
+	Czech,
+	French,
+	Italian,
+	Japanese,
+	Korean,
+	Portuguese,
+	Spanish,
+}
+
+impl FromStr for Language {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		match s.to_lowercase().replace(['-', ' '], "_").as_str() {
+			"english" | "en" => Ok(Language::English),
+			"chinese_simplified" | "zh" | "zh_hans" => Ok(Language::ChineseSimplified),
+			"chinese_traditional" | "zh_hant" => Ok(Language::ChineseTraditional),
+			"czech" | "cs" => Ok(Language::Czech),
+			"french" | "fr" => Ok(Language::French),
+			"italian" | "it" => Ok(Language::Italian),
+			"japanese" | "ja" => Ok(Language::Japanese),
+			"korean" | "ko" => Ok(Language::Korean),
+			"portuguese" | "pt" => Ok(Language::Portuguese),
+			"spanish" | "es" => Ok(Language::Spanish),
+			_ => Err(format!("Unsupported mnemonic language: {}", s)),
+		}
+	}
+}
+
+// Runs the generic `$f` with the wordlist of `$language`.
+macro_rules! with_wordlist {
+	($language:expr, $f:ident($($arg:expr),*)) => {
+		match $language {
+			Language::English => $f::<English>($($arg),*),
+			Language::ChineseSimplified => $f::<ChineseSimplified>($($arg),*),
+			Language::ChineseTraditional => $f::<ChineseTraditional>($($arg),*),
+			Language::Czech => $f::<Czech>($($arg),*),
+			Language::French => $f::<French>($($arg),*),
+			Language::Italian => $f::<Italian>($($arg),*),
+			Language::Japanese => $f::<Japanese>($($arg),*),
+			Language::Korean => $f::<Korean>($($arg),*),
+			Language::Portuguese => $f::<Portuguese>($($arg),*),
+			Language::Spanish => $f::<Spanish>($($arg),*),
+		}
+	};
+}
+
+// A new random mnemonic of `word_count` words (12, 15, 18, 21 or 24).
+pub fn generate_mnemonic(language: Language, word_count: usize) -> Result<Zeroizing<String>> {
+	fn generate<W: Wordlist>(word_count: usize) -> Result<Zeroizing<String>> {
+		let mnemonic = Mnemonic::<W>::new_with_count(&mut rand::thread_rng(), word_count).map_err(|_| format!("Invalid word count: {}", word_count))?;
+		Ok(Zeroizing::new(mnemonic.to_phrase()))
+	}
+	with_wordlist!(language, generate(word_count))
+}
+
+// The wallets at each of `paths` of the HD wallet of `phrase`. Errors never quote the phrase.
+pub fn derive_wallets(phrase: &str, language: Language, passphrase: Option<&str>, paths: &[String]) -> Result<Vec<LocalWallet>> {
+	fn derive<W: Wordlist>(phrase: &str, passphrase: Option<&str>, paths: &[String]) -> Result<Vec<LocalWallet>> {
+		let mnemonic = Mnemonic::<W>::new_from_phrase(phrase).map_err(|_| "Invalid mnemonic phrase")?;
+		let master_key = mnemonic.master_key(passphrase).map_err(|_| "Invalid mnemonic phrase")?;
+		paths
+			.iter()
+			.map(|path| {
+				let key = master_key.derive_path(path.as_str()).map_err(|err| format!("Invalid derivation path {}: {}", path, err))?;
+				Ok(LocalWallet::from(AsRef::<SigningKey>::as_ref(&key).clone()))
+			})
+			.collect()
+	}
+	with_wordlist!(language, derive(phrase.trim(), passphrase, paths))
+}
+
+// `count` consecutive account paths under `base_path`, starting at `start`.
+pub fn account_paths(base_path: &str, start: u32, count: u32) -> Result<Vec<String>> {
+	if count == 0 || count > MAX_HD_ACCOUNTS {
+		return Err(format!("count must be between 1 and {}", MAX_HD_ACCOUNTS).into());
+	}
+	let end = start.checked_add(count).ok_or("Account index out of range")?;
+	Ok((start..end).map(|index| format!("{}/{}", base_path.trim_end_matches('/'), index)).collect())
+}
@@ -0,0 +1,163 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use hmac::Hmac;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+use ethers_core::rand::{self, RngCore};
+use ethers_core::utils::{hex, keccak256};
+use ethers_signers::{LocalWallet, Signer};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+// scrypt cost of the keystores written here, the "light" parameters of geth and ethers, as the full
+// ones need 256 MB per key.
+const SCRYPT_LOG_N: u8 = 13;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+// A Web3 Secret Storage (version 3) keystore, the JSON written by geth, clef and most wallets.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Keystore {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub address: Option<String>,
+	pub crypto: KeystoreCrypto,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<String>,
+	pub version: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeystoreCrypto {
+	pub cipher: String,
+	pub cipherparams: CipherParams,
+	#[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+	pub ciphertext: Vec<u8>,
+	#[serde(flatten)]
+	pub kdf: Kdf,
+	#[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+	pub mac: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CipherParams {
+	#[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+	pub iv: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+pub enum Kdf {
+	Scrypt {
+		dklen: usize,
+		n: u64,
+		r: u32,
+		p: u32,
+		#[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+		salt: Vec<u8>,
+	},
+	Pbkdf2 {
+		c: u32,
+		dklen: usize,
+		prf: String,
+		#[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+		salt: Vec<u8>,
+	},
+}
+
+fn to_hex<S: Serializer>(bytes: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+	serializer.serialize_str(&hex::encode(bytes))
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<u8>, D::Error> {
+	let value = String::deserialize(deserializer)?;
+	hex::decode(value.trim_start_matches("0x")).map_err(serde::de::Error::custom)
+}
+
+impl Kdf {
+	fn derive_key(&self, password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+		match self {
+			Kdf::Scrypt { dklen, n, r, p, salt } => {
+				if !n.is_power_of_two() {
+					return Err("Keystore scrypt n is not a power of two".into());
+				}
+				let params = scrypt::Params::new(n.trailing_zeros() as u8, *r, *p).map_err(|err| err.to_string())?;
+				let mut key = Zeroizing::new(vec![0u8; *dklen]);
+				scrypt::scrypt(password, salt, &params, &mut key).map_err(|err| err.to_string())?;
+				Ok(key)
+			}
+			Kdf::Pbkdf2 { c, dklen, prf, salt } => {
+				if prf != "hmac-sha256" {
+					return Err(format!("Unsupported keystore pbkdf2 prf {}", prf).into());
+				}
+				let mut key = Zeroizing::new(vec![0u8; *dklen]);
+				pbkdf2::pbkdf2::<Hmac<Sha256>>(password, salt, *c, &mut key);
+				Ok(key)
+			}
+		}
+	}
+}
+
+impl Keystore {
+	// The private key, once the password is checked against the keystore MAC.
+	pub fn decrypt(&self, password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+		if self.version != 3 {
+			return Err(format!("Unsupported keystore version {}", self.version).into());
+		}
+		if self.crypto.cipher != "aes-128-ctr" {
+			return Err(format!("Unsupported keystore cipher {}", self.crypto.cipher).into());
+		}
+		let key = self.crypto.kdf.derive_key(password)?;
+		if key.len() < 32 || self.crypto.cipherparams.iv.len() != 16 {
+			return Err("Malformed keystore".into());
+		}
+		if keccak256([&key[16..32], &self.crypto.ciphertext[..]].concat()).as_slice() != self.crypto.mac.as_slice() {
+			return Err("Wrong keystore password".into());
+		}
+		let mut private_key = Zeroizing::new(self.crypto.ciphertext.clone());
+		Aes128Ctr::new(key[..16].into(), self.crypto.cipherparams.iv[..].into()).apply_keystream(&mut private_key);
+		Ok(private_key)
+	}
+}
+
+// Encrypts the key of `wallet` with `password` into a new keystore, using scrypt and aes-128-ctr.
+pub fn encrypt_keystore(wallet: &LocalWallet, password: &[u8]) -> Result<Keystore> {
+	let mut rng = rand::thread_rng();
+	let mut salt = vec![0u8; 32];
+	rng.fill_bytes(&mut salt);
+	let mut iv = vec![0u8; 16];
+	rng.fill_bytes(&mut iv);
+	let kdf = Kdf::Scrypt { dklen: 32, n: 1 << SCRYPT_LOG_N, r: SCRYPT_R, p: SCRYPT_P, salt };
+	let key = kdf.derive_key(password)?;
+
+	let mut ciphertext = Zeroizing::new(wallet.signer().to_bytes().to_vec());
+	Aes128Ctr::new(key[..16].into(), iv[..].into()).apply_keystream(&mut ciphertext);
+	let mac = keccak256([&key[16..32], &ciphertext[..]].concat()).to_vec();
+
+	Ok(Keystore {
+		address: Some(hex::encode(wallet.address())),
+		crypto: KeystoreCrypto {
+			cipher: "aes-128-ctr".to_string(),
+			cipherparams: CipherParams { iv },
+			ciphertext: ciphertext.to_vec(),
+			kdf,
+			mac,
+		},
+		id: Some(random_uuid()),
+		version: 3,
+	})
+}
+
+// A random (version 4) UUID.
+fn random_uuid() -> String {
+	let mut bytes = [0u8; 16];
+	rand::thread_rng().fill_bytes(&mut bytes);
+	bytes[6] = (bytes[6] & 0x0f) | 0x40;
+	bytes[8] = (bytes[8] & 0x3f) | 0x80;
+	let hex = hex::encode(bytes);
+	format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+pub fn decrypt_keystore(keystore: &str, password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+	serde_json::from_str::<Keystore>(keystore)?.decrypt(password)
+}
@@ -0,0 +1,99 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+use ethers_signers::{LocalWallet, Signer};
+use ethers_core::types::Address;
+use ethers_core::utils::hex;
+use zeroize::Zeroizing;
+
+mod hd;
+mod keystore;
+mod signer;
+pub use hd::*;
+pub use keystore::*;
+pub use signer::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// The configured transaction signer: with `from_index`, that account of the HD wallet of load_hd_wallet;
+// otherwise the remote signer at REMOTE_SIGNER_URL when set, signing as REMOTE_SIGNER_ADDRESS (or its
+// first account) with REMOTE_SIGNER_METHOD (default eth_signTransaction, account_signTransaction for
+// clef), or else the local wallet of load_wallet.
+pub async fn load_signer(chain_id: u64, from_index: Option<u32>) -> Result<Box<dyn TransactionSigner>> {
+	if let Some(index) = from_index {
+		return Ok(Box::new(load_hd_wallet(index, chain_id)?));
+	}
+	match std::env::var("REMOTE_SIGNER_URL") {
+		Ok(url) => {
+			let address = std::env::var("REMOTE_SIGNER_ADDRESS").ok().map(|address| Address::from_str(&address)).transpose()?;
+			let method = std::env::var("REMOTE_SIGNER_METHOD").unwrap_or("eth_signTransaction".to_string());
+			Ok(Box::new(RemoteSigner::connect(&url, address, &method).await?))
+		}
+		Err(_) => Ok(Box::new(load_wallet(chain_id)?)),
+	}
+}
+
+// The configured signing key: the Web3 Secret Storage keystore in KEYSTORE_PATH (or inline in
+// KEYSTORE_JSON) unlocked with KEYSTORE_PASSWORD (or the contents of KEYSTORE_PASSWORD_FILE), or else
+// the hex key in PRIVATE_KEY. Key material is zeroized once the wallet is built, and errors never quote it.
+// Unlocking a keystore runs its KDF (scrypt takes about a second at geth's default cost), so the wallet is
+// built once and kept for the life of the process.
+pub fn load_wallet(chain_id: u64) -> Result<LocalWallet> {
+	static WALLET: OnceLock<LocalWallet> = OnceLock::new();
+	// Failures are not kept, so the next request tries again.
+	let wallet = match WALLET.get() {
+		Some(wallet) => wallet,
+		None => {
+			let wallet = unlock_wallet()?;
+			WALLET.get_or_init(|| wallet)
+		}
+	};
+	Ok(wallet.clone().with_chain_id(chain_id))
+}
+
+fn unlock_wallet() -> Result<LocalWallet> {
+	let private_key = match keystore_json()? {
+		Some(keystore) => decrypt_keystore(&keystore, keystore_password()?.as_bytes())?,
+		None => {
+			let private_key = Zeroizing::new(std::env::var("PRIVATE_KEY").map_err(|_| "No signer configured, set KEYSTORE_PATH or PRIVATE_KEY")?);
+			Zeroizing::new(hex::decode(private_key.trim().trim_start_matches("0x")).map_err(|_| "PRIVATE_KEY is not a hex string")?)
+		}
+	};
+	Ok(LocalWallet::from_bytes(&private_key).map_err(|_| "The configured private key is not a valid secp256k1 key")?)
+}
+
+// Account `index` of the HD wallet of the mnemonic in MNEMONIC (or the file at MNEMONIC_FILE), in
+// MNEMONIC_LANGUAGE (default english) with the optional MNEMONIC_PASSPHRASE, under HD_PATH (default
+// m/44'/60'/0'/0).
+pub fn load_hd_wallet(index: u32, chain_id: u64) -> Result<LocalWallet> {
+	let phrase = match std::env::var("MNEMONIC") {
+		Ok(phrase) => Zeroizing::new(phrase),
+		Err(_) => {
+			let path = std::env::var("MNEMONIC_FILE").map_err(|_| "No HD wallet configured, set MNEMONIC or MNEMONIC_FILE")?;
+			Zeroizing::new(std::fs::read_to_string(&path).map_err(|err| format!("Failed to read mnemonic {}: {}", path, err))?)
+		}
+	};
+	let language = std::env::var("MNEMONIC_LANGUAGE").map(|language| Language::from_str(&language)).unwrap_or(Ok(Language::English))?;
+	let passphrase = std::env::var("MNEMONIC_PASSPHRASE").ok().map(Zeroizing::new);
+	let base_path = std::env::var("HD_PATH").unwrap_or(DEFAULT_HD_PATH.to_string());
+	let wallet = derive_wallets(&phrase, language, passphrase.as_deref().map(String::as_str), &account_paths(&base_path, index, 1)?)?.remove(0);
+	Ok(wallet.with_chain_id(chain_id))
+}
+
+fn keystore_json() -> Result<Option<String>> {
+	if let Ok(keystore) = std::env::var("KEYSTORE_JSON") {
+		return Ok(Some(keystore));
+	}
+	match std::env::var("KEYSTORE_PATH") {
+		Ok(path) => Ok(Some(std::fs::read_to_string(&path).map_err(|err| format!("Failed to read keystore {}: {}", path, err))?)),
+		Err(_) => Ok(None),
+	}
+}
+
+fn keystore_password() -> Result<Zeroizing<String>> {
+	if let Ok(password) = std::env::var("KEYSTORE_PASSWORD") {
+		return Ok(Zeroizing::new(password));
+	}
+	let path = std::env::var("KEYSTORE_PASSWORD_FILE").map_err(|_| "Set KEYSTORE_PASSWORD or KEYSTORE_PASSWORD_FILE to unlock the keystore")?;
+	let content = Zeroizing::new(std::fs::read_to_string(&path).map_err(|err| format!("Failed to read keystore password {}: {}", path, err))?);
+	Ok(Zeroizing::new(content.trim_end_matches(['\r', '\n']).to_string()))
+}
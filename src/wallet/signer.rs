@@ -0,0 +1,273 @@
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use ethers_core::types::{transaction::eip2718::TypedTransaction, Address, Bytes, Signature};
+use ethers_core::utils::hex;
+use ethers_signers::{LocalWallet, Signer};
+use crate::ether_lib::json_rpc_direct;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub type SignerFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
+
+// Signs the transactions built by wrap_transaction, in this process or elsewhere.
+pub trait TransactionSigner {
+	fn address(&self) -> Address;
+	// The signed transaction, RLP encoded and ready for eth_sendRawTransaction.
+	fn sign_transaction<'a>(&'a self, tx: &'a TypedTransaction) -> SignerFuture<'a, Bytes>;
+	// EIP-191 personal_sign signature of `message`.
+	fn sign_message<'a>(&'a self, message: &'a [u8]) -> SignerFuture<'a, Signature>;
+}
+
+// A key held in this process, from PRIVATE_KEY or an unlocked keystore.
+impl TransactionSigner for LocalWallet {
+	fn address(&self) -> Address {
+		Signer::address(self)
+	}
+
+	fn sign_transaction<'a>(&'a self, tx: &'a TypedTransaction) -> SignerFuture<'a, Bytes> {
+		Box::pin(async move {
+			let signature = Signer::sign_transaction(self, tx).await?;
+			Ok(tx.rlp_signed(&signature))
+		})
+	}
+
+	fn sign_message<'a>(&'a self, message: &'a [u8]) -> SignerFuture<'a, Signature> {
+		Box::pin(async move { Ok(Signer::sign_message(self, message).await?) })
+	}
+}
+
+// Carries the JSON-RPC calls of a RemoteSigner.
+pub trait SignerTransport {
+	fn call<'a>(&'a self, method: &'a str, params: Value) -> SignerFuture<'a, Value>;
+}
+
+// Posts every call once to the signer at `url`, bypassing the node cache and failover.
+pub struct HttpSignerTransport {
+	url: String,
+}
+
+impl HttpSignerTransport {
+	pub fn new(url: &str) -> Self {
+		HttpSignerTransport { url: url.to_string() }
+	}
+}
+
+impl SignerTransport for HttpSignerTransport {
+	fn call<'a>(&'a self, method: &'a str, params: Value) -> SignerFuture<'a, Value> {
+		Box::pin(async move { Ok(json_rpc_direct(&self.url, method, params).await?) })
+	}
+}
+
+// A signer outside the function runtime, such as Web3Signer (eth_signTransaction) or clef
+// (account_signTransaction), reached over JSON-RPC.
+pub struct RemoteSigner {
+	transport: Box<dyn SignerTransport>,
+	address: Address,
+	method: String,
+}
+
+impl RemoteSigner {
+	pub fn new(url: &str, address: Address, method: &str) -> Self {
+		RemoteSigner::with_transport(Box::new(HttpSignerTransport::new(url)), address, method)
+	}
+
+	pub fn with_transport(transport: Box<dyn SignerTransport>, address: Address, method: &str) -> Self {
+		RemoteSigner { transport, address, method: method.to_string() }
+	}
+
+	// Signs as `address`, or as the first account the signer reports when not given.
+	pub async fn connect(url: &str, address: Option<Address>, method: &str) -> Result<Self> {
+		let transport = HttpSignerTransport::new(url);
+		let address = match address {
+			Some(address) => address,
+			None => {
+				let accounts = transport.call("eth_accounts", json!([])).await?;
+				let account = accounts.as_array().and_then(|accounts| accounts.first()).and_then(Value::as_str).ok_or("Remote signer has no account")?;
+				Address::from_str(account)?
+			}
+		};
+		Ok(RemoteSigner::with_transport(Box::new(transport), address, method))
+	}
+}
+
+impl TransactionSigner for RemoteSigner {
+	fn address(&self) -> Address {
+		self.address
+	}
+
+	fn sign_transaction<'a>(&'a self, tx: &'a TypedTransaction) -> SignerFuture<'a, Bytes> {
+		Box::pin(async move {
+			let mut request = tx.clone();
+			request.set_from(self.address);
+			// ethers leaves chainId out of serialized requests, but the signer needs it for replay protection.
+			let mut request = serde_json::to_value(request)?;
+			if let Some(chain_id) = tx.chain_id() {
+				request["chainId"] = json!(format!("{:#x}", chain_id));
+			}
+			let result = self.transport.call(&self.method, json!([request])).await?;
+			// Web3Signer answers with the raw transaction, geth and clef with {raw, tx}.
+			let raw = result.as_str().or(result["raw"].as_str()).ok_or("Remote signer returned no raw transaction")?;
+			let raw = Bytes::from(hex::decode(raw.trim_start_matches("0x"))?);
+
+			// Only send what was asked for, signed by the expected account. The sighash covers every field,
+			// fees and gas included.
+			let (signed, signature) = TypedTransaction::decode_signed(&rlp::Rlp::new(&raw))?;
+			if signature.recover(signed.sighash())? != self.address {
+				return Err("Remote signer signed with another account".into());
+			}
+			if signed.sighash() != tx.sighash() {
+				return Err("Remote signer changed the transaction".into());
+			}
+			Ok(raw)
+		})
+	}
+
+	fn sign_message<'a>(&'a self, message: &'a [u8]) -> SignerFuture<'a, Signature> {
+		Box::pin(async move {
+			let data = format!("0x{}", hex::encode(message));
+			// clef signs text with account_signData, Web3Signer and geth with eth_sign.
+			let result = match self.method.starts_with("account_") {
+				true => self.transport.call("account_signData", json!(["text/plain", self.address, data])).await?,
+				false => self.transport.call("eth_sign", json!([self.address, data])).await?,
+			};
+			let signature = Signature::from_str(result.as_str().ok_or("Remote signer returned no signature")?)?;
+			if signature.recover(message)? != self.address {
+				return Err("Remote signer signed with another account".into());
+			}
+			Ok(signature)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethers_core::types::{Eip1559TransactionRequest, TransactionRequest, U256};
+
+	const CHAIN_ID: u64 = 11155111;
+
+	// Stands in for Web3Signer: reports the account of `wallet` to eth_accounts and answers eth_signTransaction
+	// with the raw transaction signed by `wallet`, after `tamper` has had its way with the request.
+	struct StandIn {
+		wallet: LocalWallet,
+		tamper: fn(&mut TypedTransaction),
+	}
+
+	impl SignerTransport for StandIn {
+		fn call<'a>(&'a self, method: &'a str, params: Value) -> SignerFuture<'a, Value> {
+			Box::pin(async move {
+				match method {
+					"eth_accounts" => Ok(json!([Signer::address(&self.wallet)])),
+					"eth_signTransaction" => {
+						let mut tx: TypedTransaction = serde_json::from_value(params[0].clone())?;
+						(self.tamper)(&mut tx);
+						let signature = self.wallet.sign_transaction_sync(&tx)?;
+						Ok(json!(format!("0x{}", hex::encode(tx.rlp_signed(&signature)))))
+					}
+					_ => Err(format!("the method {} does not exist", method).into()),
+				}
+			})
+		}
+	}
+
+	// Serves `signer` as a JSON-RPC endpoint over HTTP, one call per connection.
+	async fn serve(listener: tokio::net::TcpListener, signer: StandIn) {
+		use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+		loop {
+			let (stream, _) = listener.accept().await.unwrap();
+			let mut stream = BufReader::new(stream);
+			let mut length = 0;
+			let mut line = String::new();
+			while stream.read_line(&mut line).await.unwrap() > 0 && line != "\r\n" {
+				if let Some((_, value)) = line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+					length = value.trim().parse().unwrap();
+				}
+				line.clear();
+			}
+			let mut body = vec![0; length];
+			stream.read_exact(&mut body).await.unwrap();
+			let request: Value = serde_json::from_slice(&body).unwrap();
+			let response = match signer.call(request["method"].as_str().unwrap(), request["params"].clone()).await {
+				Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+				Err(err) => json!({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -32601, "message": err.to_string()}}),
+			}.to_string();
+			let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", response.len(), response);
+			stream.get_mut().write_all(response.as_bytes()).await.unwrap();
+		}
+	}
+
+	fn wallet(key: u8) -> LocalWallet {
+		LocalWallet::from_bytes(&[key; 32]).unwrap().with_chain_id(CHAIN_ID)
+	}
+
+	// A remote signer for the account of wallet(1), served by a stand-in signing with `signing_wallet`.
+	fn remote_signer(signing_wallet: LocalWallet, tamper: fn(&mut TypedTransaction)) -> RemoteSigner {
+		RemoteSigner::with_transport(Box::new(StandIn { wallet: signing_wallet, tamper }), Signer::address(&wallet(1)), "eth_signTransaction")
+	}
+
+	fn transactions() -> Vec<TypedTransaction> {
+		let to = Address::from_low_u64_be(2);
+		vec![
+			TransactionRequest::new().to(to).value(1000).data(vec![1, 2]).gas(21000).gas_price(2_000_000_000u64).nonce(7).chain_id(CHAIN_ID).into(),
+			Eip1559TransactionRequest::new().to(to).value(1000).data(vec![1, 2]).gas(21000).max_fee_per_gas(3_000_000_000u64).max_priority_fee_per_gas(1_000_000_000u64).nonce(7).chain_id(CHAIN_ID).into(),
+		]
+	}
+
+	async fn sign(signer: &RemoteSigner, tx: &TypedTransaction) -> std::result::Result<TypedTransaction, String> {
+		let raw = TransactionSigner::sign_transaction(signer, tx).await.map_err(|err| err.to_string())?;
+		Ok(TypedTransaction::decode_signed(&rlp::Rlp::new(&raw)).unwrap().0)
+	}
+
+	#[tokio::test]
+	async fn accepts_requested_transaction_signed_by_account() {
+		for tx in transactions() {
+			let signed = sign(&remote_signer(wallet(1), |_| {}), &tx).await.unwrap();
+			assert_eq!(signed.sighash(), tx.sighash());
+		}
+	}
+
+	#[tokio::test]
+	async fn rejects_signature_of_another_account() {
+		for tx in transactions() {
+			assert_eq!(sign(&remote_signer(wallet(2), |_| {}), &tx).await.unwrap_err(), "Remote signer signed with another account");
+		}
+	}
+
+	#[tokio::test]
+	async fn rejects_modified_transaction() {
+		let tampers: [fn(&mut TypedTransaction); 5] = [
+			|tx| { tx.set_to(Address::from_low_u64_be(3)); },
+			|tx| { tx.set_value(U256::from(10).pow(18.into())); },
+			|tx| { tx.set_gas(1_000_000); },
+			|tx| { tx.set_gas_price(100_000_000_000u64); },
+			|tx| if let TypedTransaction::Eip1559(tx) = tx { tx.max_priority_fee_per_gas = Some(2_000_000_000u64.into()); } else { tx.set_nonce(8); },
+		];
+		for tx in transactions() {
+			for tamper in tampers {
+				assert_eq!(sign(&remote_signer(wallet(1), tamper), &tx).await.unwrap_err(), "Remote signer changed the transaction");
+			}
+		}
+	}
+
+	// Through HttpSignerTransport and a real HTTP exchange rather than a stand-in transport.
+	#[tokio::test]
+	async fn signs_through_http_transport() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let client = async {
+			let signer = RemoteSigner::connect(&url, None, "eth_signTransaction").await.unwrap();
+			assert_eq!(TransactionSigner::address(&signer), Signer::address(&wallet(1)));
+			for tx in transactions() {
+				assert_eq!(sign(&signer, &tx).await.unwrap().sighash(), tx.sighash());
+			}
+			let err = TransactionSigner::sign_message(&signer, b"hello").await.unwrap_err().to_string();
+			assert!(err.contains("the method eth_sign does not exist"), "{}", err);
+		};
+		tokio::select! {
+			_ = serve(listener, StandIn { wallet: wallet(1), tamper: |_| {} }) => unreachable!(),
+			_ = client => {}
+		}
+	}
+}